@@ -0,0 +1,149 @@
+use crate::error::SwapError;
+use crate::pda::find_config_address;
+use solana_program::account_info::AccountInfo;
+use solana_program::entrypoint::ProgramResult;
+use solana_program::program_error::ProgramError;
+use solana_program::pubkey::Pubkey;
+use solana_program::system_program;
+
+/// Most token programs the config can allow SPL token payments to be made with.
+pub const MAX_TOKEN_PROGRAMS: usize = 4;
+/// The dex fee rate is expressed in basis points of the payment amount.
+pub const DEX_FEE_RATE_DENOMINATOR: u64 = 10_000;
+
+/// Settings the config admin controls, read by the payment instructions.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ConfigSettings {
+    /// Account dex fees are paid to, the owner of the token account they are paid to for SPL
+    /// token payments. The default pubkey while no dex fee can be paid.
+    pub dex_fee_account: Pubkey,
+    /// Lowest dex fee, in basis points of the payment amount.
+    pub dex_fee_rate: u16,
+    /// Lowest amount a payment can be made for.
+    pub min_amount: u64,
+    /// Token programs SPL token payments can be made with, at most [`MAX_TOKEN_PROGRAMS`].
+    pub allowed_token_programs: Vec<Pubkey>,
+}
+
+impl Default for ConfigSettings {
+    /// The settings the program had before it was configurable.
+    fn default() -> Self {
+        Self {
+            dex_fee_account: Pubkey::default(),
+            dex_fee_rate: 0,
+            min_amount: 0,
+            allowed_token_programs: vec![spl_token::id(), spl_token_2022::id()],
+        }
+    }
+}
+
+impl ConfigSettings {
+    pub fn validate(&self) -> Result<(), SwapError> {
+        if u64::from(self.dex_fee_rate) > DEX_FEE_RATE_DENOMINATOR
+            || self.allowed_token_programs.len() > MAX_TOKEN_PROGRAMS
+        {
+            return Err(SwapError::InvalidConfig);
+        }
+        Ok(())
+    }
+
+    /// Lowest dex fee of a payment of `amount`, rounded up and never 0 so that a payment with a dex
+    /// fee always pays one.
+    pub fn min_dex_fee(&self, amount: u64) -> u64 {
+        let fee = (u128::from(amount) * u128::from(self.dex_fee_rate))
+            .div_ceil(u128::from(DEX_FEE_RATE_DENOMINATOR));
+        (fee as u64).max(1)
+    }
+
+    /// `[dex_fee_account, dex_fee_rate, min_amount, token program count, token programs..]`, the
+    /// layout shared by the config account and the config instructions.
+    pub fn pack_into(&self, bytes: &mut Vec<u8>) {
+        bytes.extend_from_slice(self.dex_fee_account.as_ref());
+        bytes.extend_from_slice(&self.dex_fee_rate.to_le_bytes());
+        bytes.extend_from_slice(&self.min_amount.to_le_bytes());
+        bytes.push(self.allowed_token_programs.len() as u8);
+        for token_program in &self.allowed_token_programs {
+            bytes.extend_from_slice(token_program.as_ref());
+        }
+    }
+}
+
+/// Program wide configuration, held by the account at [`find_config_address`].
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Config {
+    /// Signer of the config updates, the default pubkey while the config is not initialized.
+    pub admin: Pubkey,
+    /// Admin proposed by the current one, who takes over once it accepts. The default pubkey
+    /// while none is proposed.
+    pub pending_admin: Pubkey,
+    pub settings: ConfigSettings,
+    /// Whether new payments are rejected, spends and refunds of existing ones keep working.
+    pub paused: bool,
+}
+
+impl Config {
+    /// The admin and the pending admin followed by the settings, with room for
+    /// [`MAX_TOKEN_PROGRAMS`] token programs, and the paused flag.
+    pub const LEN: usize = 32 + 32 + 32 + 2 + 8 + 1 + 32 * MAX_TOKEN_PROGRAMS + 1;
+
+    pub fn pack(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(Self::LEN);
+        bytes.extend_from_slice(self.admin.as_ref());
+        bytes.extend_from_slice(self.pending_admin.as_ref());
+        self.settings.pack_into(&mut bytes);
+        bytes.resize(Self::LEN - 1, 0);
+        bytes.push(self.paused as u8);
+        bytes
+    }
+
+    pub fn unpack(input: &[u8]) -> Result<Self, ProgramError> {
+        if input.len() != Self::LEN {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        let pubkey = |offset: usize| {
+            Pubkey::new_from_array(
+                input[offset..offset + 32]
+                    .try_into()
+                    .expect("config holds the field"),
+            )
+        };
+        let count = input[106] as usize;
+        if count > MAX_TOKEN_PROGRAMS {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        Ok(Self {
+            admin: pubkey(0),
+            pending_admin: pubkey(32),
+            settings: ConfigSettings {
+                dex_fee_account: pubkey(64),
+                dex_fee_rate: u16::from_le_bytes([input[96], input[97]]),
+                min_amount: u64::from_le_bytes(
+                    input[98..106].try_into().expect("config holds the field"),
+                ),
+                allowed_token_programs: (0..count).map(|index| pubkey(107 + 32 * index)).collect(),
+            },
+            paused: input[Self::LEN - 1] != 0,
+        })
+    }
+
+    /// Reads the config from `config_account`, the default one while it is not initialized.
+    pub fn load(program_id: &Pubkey, config_account: &AccountInfo) -> Result<Self, ProgramError> {
+        if *config_account.key != find_config_address(program_id).0 {
+            return Err(SwapError::InvalidConfigAccount.into());
+        }
+        if config_account.owner == program_id {
+            return Self::unpack(&config_account.try_borrow_data()?);
+        }
+        if config_account.owner == &system_program::ID && config_account.data_is_empty() {
+            return Ok(Self::default());
+        }
+        Err(SwapError::InvalidConfigAccount.into())
+    }
+
+    pub fn pack_into_account(&self, config_account: &AccountInfo) -> ProgramResult {
+        config_account
+            .try_borrow_mut_data()?
+            .copy_from_slice(&self.pack());
+        Ok(())
+    }
+}
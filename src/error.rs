@@ -0,0 +1,147 @@
+use num_derive::FromPrimitive;
+use num_traits::FromPrimitive;
+use solana_program::decode_error::DecodeError;
+use solana_program::instruction::InstructionError;
+use solana_program::msg;
+use solana_program::program_error::{PrintProgramError, ProgramError};
+use thiserror::Error;
+
+#[derive(Clone, Copy, Debug, Eq, Error, FromPrimitive, PartialEq)]
+pub enum SwapError {
+    #[error("Invalid instruction data length")]
+    InvalidInputLength = 601,
+    #[error("Invalid secret hash")]
+    InvalidSecretHash = 602,
+    #[error("Invalid lock time")]
+    InvalidLockTime = 603,
+    #[error("Invalid amount")]
+    InvalidAmount = 604,
+    #[error("Invalid receiver pubkey")]
+    InvalidReceiverPubkey = 605,
+    #[error("Invalid token program")]
+    InvalidTokenProgram = 606,
+    #[error("Invalid secret")]
+    InvalidSecret = 607,
+    #[error("Invalid sender pubkey")]
+    InvalidSenderPubkey = 608,
+    #[error("Unknown atomic swap instruction")]
+    InvalidAtomicSwapInstruction = 609,
+    #[error("Receiver is set to the default pubkey")]
+    ReceiverSetToDefault = 610,
+    #[error("Amount is zero")]
+    AmountZero = 611,
+    #[error("Swap account not found")]
+    SwapAccountNotFound = 612,
+    #[error("Payment hash does not match the stored one")]
+    InvalidPaymentHash = 613,
+    #[error("Payment is not in the expected state")]
+    InvalidPaymentState = 614,
+    #[error("Token program is not supported")]
+    NotSupported = 615,
+    #[error("Swap account is not owned by the program")]
+    InvalidOwner = 616,
+    #[error("Lock time has not passed yet")]
+    WaitForLockTime = 617,
+    #[error("Sender account is not a signer")]
+    SenderAccountNotSigner = 618,
+    #[error("Sender account is not writable")]
+    SenderAccountNotWritable = 619,
+    #[error("Vault data account is not writable")]
+    VaultPdaDataNotWritable = 620,
+    #[error("Vault account is not writable")]
+    VaultPdaNotWritable = 621,
+    #[error("Vault account is not owned by the system program")]
+    VaultPdaNotSystemOwned = 622,
+    #[error("Invalid mint")]
+    InvalidMint = 623,
+    #[error("Token account has the wrong owner")]
+    InvalidTokenAccountOwner = 624,
+    #[error("Mint is non-transferable")]
+    NonTransferableMint = 625,
+    #[error("Mints with a transfer hook are not supported")]
+    TransferHookNotSupported = 626,
+    #[error("Mints with a permanent delegate are not supported")]
+    PermanentDelegateNotSupported = 627,
+    #[error("Vault address does not match the payment seeds")]
+    VaultAddressMismatch = 628,
+    #[error("Vault data address does not match the payment seeds")]
+    VaultDataAddressMismatch = 629,
+    #[error("Bump seed is not canonical")]
+    NonCanonicalBump = 630,
+    #[error("Unsupported instruction encoding version")]
+    UnsupportedInstructionVersion = 631,
+    #[error("Unknown secret hash algorithm")]
+    InvalidSecretHashAlgorithm = 632,
+    #[error("Secret is longer than the maximum supported length")]
+    SecretTooLong = 633,
+    #[error("Spend deadline has passed")]
+    SpendDeadlinePassed = 634,
+    #[error("Spend deadline is after the lock time")]
+    InvalidSpendDeadline = 635,
+    #[error("Payment account does not store the swap terms")]
+    PaymentTermsNotStored = 636,
+    #[error("Relayer fee is not lower than the amount")]
+    InvalidRelayerFee = 637,
+    #[error("Dex fee account does not match the configured one")]
+    InvalidDexFeeAccount = 638,
+    #[error("Config account is not the program's config")]
+    InvalidConfigAccount = 639,
+    #[error("Signer is not allowed to update the config")]
+    UnauthorizedConfigUpdate = 640,
+    #[error("Invalid config settings")]
+    InvalidConfig = 641,
+    #[error("Amount is below the configured minimum")]
+    AmountBelowMinimum = 642,
+    #[error("Token program is not allowed by the config")]
+    TokenProgramNotAllowed = 643,
+    #[error("Dex fee is below the minimum for the payment amount")]
+    DexFeeTooLow = 644,
+    #[error("New payments are paused")]
+    PaymentsPaused = 645,
+    #[error("Config admin can't be the default pubkey")]
+    InvalidConfigAdmin = 646,
+}
+
+impl From<SwapError> for ProgramError {
+    fn from(e: SwapError) -> Self {
+        ProgramError::Custom(e as u32)
+    }
+}
+
+impl<T> DecodeError<T> for SwapError {
+    fn type_of() -> &'static str {
+        "SwapError"
+    }
+}
+
+impl PrintProgramError for SwapError {
+    fn print<E>(&self) {
+        msg!("SwapError: {}", self);
+    }
+}
+
+impl SwapError {
+    pub fn from_code(code: u32) -> Option<Self> {
+        Self::from_u32(code)
+    }
+
+    pub fn from_instruction_error(error: &InstructionError) -> Option<Self> {
+        match error {
+            InstructionError::Custom(code) => Self::from_code(*code),
+            _ => None,
+        }
+    }
+
+    /// Decodes the error a failed swap transaction was rejected with, for clients.
+    #[cfg(not(target_os = "solana"))]
+    pub fn from_transaction_error(
+        error: &solana_sdk::transaction::TransactionError,
+    ) -> Option<Self> {
+        match error {
+            solana_sdk::transaction::TransactionError::InstructionError(_, error) => {
+                Self::from_instruction_error(error)
+            }
+            _ => None,
+        }
+    }
+}
@@ -1,22 +0,0 @@
-pub const INVALID_INPUT_LENGTH: u32 = 601;
-pub const INVALID_SECRET_HASH: u32 = 602;
-pub const INVALID_LOCK_TIME: u32 = 603;
-pub const INVALID_AMOUNT: u32 = 604;
-pub const INVALID_RECEIVER_PUBKEY: u32 = 605;
-pub const INVALID_TOKEN_PROGRAM: u32 = 606;
-pub const INVALID_SECRET: u32 = 607;
-pub const INVALID_SENDER_PUBKEY: u32 = 608;
-pub const INVALID_ATOMIC_SWAP_INSTRUCTION: u32 = 609;
-pub const RECEIVER_SET_TO_DEFAULT: u32 = 610;
-pub const AMOUNT_ZERO: u32 = 611;
-pub const SWAP_ACCOUNT_NOT_FOUND: u32 = 612;
-pub const INVALID_PAYMENT_HASH: u32 = 613;
-pub const INVALID_PAYMENT_STATE: u32 = 614;
-pub const NOT_SUPPORTED: u32 = 615;
-pub const INVALID_OWNER: u32 = 616;
-pub const WAIT_FOR_LOCK_TIME: u32 = 617;
-pub const SENDER_ACCOUNT_NOT_SIGNER: u32 = 618;
-pub const SENDER_ACCOUNT_NOT_WRITABLE: u32 = 619;
-pub const VAULT_PDA_DATA_NOT_WRITABLE: u32 = 620;
-pub const VAULT_PDA_NOT_WRITABLE: u32 = 621;
-pub const VAULT_PDA_PROGRAM_NOT_OWNER: u32 = 622;
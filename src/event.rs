@@ -0,0 +1,140 @@
+use crate::instruction::MAX_SECRET_LEN;
+use solana_program::log::sol_log_data;
+use solana_program::program_error::ProgramError;
+use solana_program::pubkey::Pubkey;
+
+/// First byte of every event, bumped whenever a released event layout changes.
+pub const EVENT_VERSION: u8 = 1;
+
+const PAYMENT_SENT: u8 = 0;
+const RECEIVER_SPENT: u8 = 1;
+const SENDER_REFUNDED: u8 = 2;
+
+/// Event logged with `sol_log_data` whenever a payment changes state, laid out as
+/// `[EVENT_VERSION, kind, payment, payment_hash, amount, mint]` followed by the dex fee for
+/// `PaymentSent` and the length-prefixed secret for `ReceiverSpent`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct SwapEvent {
+    pub kind: SwapEventKind,
+    /// Address of the vault data account holding the payment.
+    pub payment: Pubkey,
+    pub payment_hash: [u8; 32],
+    /// Amount moved into or out of the vault.
+    pub amount: u64,
+    /// The default pubkey for lamports payments.
+    pub mint: Pubkey,
+}
+
+/// `dex_fee` is the fee `PaymentWithDexFee` paid alongside the payment, zero without one.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum SwapEventKind {
+    PaymentSent { dex_fee: u64 },
+    ReceiverSpent { secret: Vec<u8> },
+    SenderRefunded,
+}
+
+impl SwapEvent {
+    const HEADER_LEN: usize = 106;
+
+    pub fn emit(&self) {
+        sol_log_data(&[&self.pack()]);
+    }
+
+    pub fn pack(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(Self::HEADER_LEN);
+        bytes.push(EVENT_VERSION);
+        bytes.push(match self.kind {
+            SwapEventKind::PaymentSent { .. } => PAYMENT_SENT,
+            SwapEventKind::ReceiverSpent { .. } => RECEIVER_SPENT,
+            SwapEventKind::SenderRefunded => SENDER_REFUNDED,
+        });
+        bytes.extend_from_slice(self.payment.as_ref());
+        bytes.extend_from_slice(&self.payment_hash);
+        bytes.extend_from_slice(&self.amount.to_le_bytes());
+        bytes.extend_from_slice(self.mint.as_ref());
+        match &self.kind {
+            SwapEventKind::PaymentSent { dex_fee } => {
+                bytes.extend_from_slice(&dex_fee.to_le_bytes())
+            }
+            SwapEventKind::ReceiverSpent { secret } => {
+                bytes.push(secret.len() as u8);
+                bytes.extend_from_slice(secret);
+            }
+            SwapEventKind::SenderRefunded => {}
+        }
+        bytes
+    }
+
+    pub fn unpack(input: &[u8]) -> Result<Self, ProgramError> {
+        if input.len() < Self::HEADER_LEN || input[0] != EVENT_VERSION {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        let (header, rest) = input.split_at(Self::HEADER_LEN);
+        let kind = match (header[1], rest) {
+            (PAYMENT_SENT, dex_fee) => SwapEventKind::PaymentSent {
+                dex_fee: u64::from_le_bytes(
+                    dex_fee
+                        .try_into()
+                        .map_err(|_| ProgramError::InvalidAccountData)?,
+                ),
+            },
+            (RECEIVER_SPENT, [len, secret @ ..])
+                if *len as usize == secret.len() && secret.len() <= MAX_SECRET_LEN =>
+            {
+                SwapEventKind::ReceiverSpent {
+                    secret: secret.to_vec(),
+                }
+            }
+            (SENDER_REFUNDED, []) => SwapEventKind::SenderRefunded,
+            _ => return Err(ProgramError::InvalidAccountData),
+        };
+        let array = |offset: usize| -> [u8; 32] {
+            header[offset..offset + 32]
+                .try_into()
+                .expect("header holds the field")
+        };
+        Ok(Self {
+            kind,
+            payment: Pubkey::new_from_array(array(2)),
+            payment_hash: array(34),
+            amount: u64::from_le_bytes(header[66..74].try_into().expect("header holds the field")),
+            mint: Pubkey::new_from_array(array(74)),
+        })
+    }
+
+    /// Decodes the events `program_id` logged in a transaction's log messages, skipping data
+    /// logged by the programs it invokes.
+    #[cfg(not(target_os = "solana"))]
+    pub fn from_logs(program_id: &Pubkey, logs: &[String]) -> Vec<Self> {
+        use base64::Engine;
+
+        let program_id = program_id.to_string();
+        let mut invoked = Vec::new();
+        let mut events = Vec::new();
+        for log in logs {
+            let Some(message) = log.strip_prefix("Program ") else {
+                continue;
+            };
+            if let Some(data) = message.strip_prefix("data: ") {
+                if invoked.last() != Some(&program_id.as_str()) {
+                    continue;
+                }
+                let event = base64::engine::general_purpose::STANDARD
+                    .decode(data)
+                    .ok()
+                    .and_then(|data| Self::unpack(&data).ok());
+                events.extend(event);
+                continue;
+            }
+            let mut words = message.split(' ');
+            match (words.next(), words.next()) {
+                (Some(program), Some("invoke")) => invoked.push(program),
+                (Some(_), Some("success" | "failed:")) => {
+                    invoked.pop();
+                }
+                _ => {}
+            }
+        }
+        events
+    }
+}
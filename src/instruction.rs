@@ -1,33 +1,55 @@
-use crate::error_code::{
-    INVALID_AMOUNT, INVALID_ATOMIC_SWAP_INSTRUCTION, INVALID_INPUT_LENGTH, INVALID_LOCK_TIME,
-    INVALID_RECEIVER_PUBKEY, INVALID_SECRET, INVALID_SECRET_HASH, INVALID_SENDER_PUBKEY,
-    INVALID_TOKEN_PROGRAM,
+use crate::config::{ConfigSettings, MAX_TOKEN_PROGRAMS};
+use crate::error::SwapError;
+use crate::lock_time::LockTimeKind;
+use crate::payment::{Payment, SecretHashAlgorithm};
+use crate::pda::{find_config_address, PaymentSeeds};
+use solana_program::instruction::{AccountMeta, Instruction};
+use solana_program::{
+    bpf_loader_upgradeable, program_error::ProgramError, pubkey::Pubkey, system_program,
 };
-use solana_program::{msg, program_error::ProgramError, pubkey::Pubkey};
 
-#[derive(Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub enum AtomicSwapInstruction {
     LamportsPayment {
         secret_hash: [u8; 32],
+        secret_hash_algorithm: SecretHashAlgorithm,
         lock_time: u64,
+        lock_time_kind: LockTimeKind,
+        spend_deadline: u64,
         amount: u64,
+        relayer_fee: u64,
+        watcher_reward: u64,
         receiver: Pubkey,
-        rent_exemption_lamports: u64,
         vault_bump_seed: u8,
         vault_bump_seed_data: u8,
     },
     SPLTokenPayment {
         secret_hash: [u8; 32],
+        secret_hash_algorithm: SecretHashAlgorithm,
         lock_time: u64,
+        lock_time_kind: LockTimeKind,
+        spend_deadline: u64,
         amount: u64,
+        relayer_fee: u64,
+        watcher_reward: u64,
         receiver: Pubkey,
         token_program: Pubkey,
-        rent_exemption_lamports: u64,
         vault_bump_seed: u8,
         vault_bump_seed_data: u8,
     },
     ReceiverSpend {
-        secret: [u8; 32],
+        secret: Vec<u8>,
+        lock_time: u64,
+        amount: u64,
+        sender: Pubkey,
+        token_program: Pubkey,
+        vault_bump_seed: u8,
+        vault_bump_seed_data: u8,
+    },
+    /// `ReceiverSpend` submitted by anyone holding the secret, paying the amount to the receiver
+    /// minus the payment's relayer fee, which goes to the submitter.
+    RelayedReceiverSpend {
+        secret: Vec<u8>,
         lock_time: u64,
         amount: u64,
         sender: Pubkey,
@@ -44,28 +66,96 @@ pub enum AtomicSwapInstruction {
         vault_bump_seed: u8,
         vault_bump_seed_data: u8,
     },
+    /// `SenderRefund` submitted by anyone once the lock time has passed, always refunding the
+    /// sender the payment was made by.
+    RelayedSenderRefund {
+        secret_hash: [u8; 32],
+        lock_time: u64,
+        amount: u64,
+        receiver: Pubkey,
+        token_program: Pubkey,
+        vault_bump_seed: u8,
+        vault_bump_seed_data: u8,
+    },
+    ClosePayment {
+        secret_hash: [u8; 32],
+        lock_time: u64,
+        amount: u64,
+        receiver: Pubkey,
+        token_program: Pubkey,
+        vault_bump_seed: u8,
+        vault_bump_seed_data: u8,
+    },
+    /// `ReceiverSpend` of a v2 payment, whose other parameters are read from its account.
+    CompactReceiverSpend { secret: Vec<u8> },
+    /// `SenderRefund` of a v2 payment, whose parameters are read from its account.
+    CompactSenderRefund,
+    /// Checks the parameters against the payment and returns its `PaymentStatus` as return data.
+    /// `mint` and `token_program` are the default pubkey for lamports payments.
+    QueryPayment {
+        secret_hash: [u8; 32],
+        lock_time: u64,
+        amount: u64,
+        sender: Pubkey,
+        receiver: Pubkey,
+        mint: Pubkey,
+        token_program: Pubkey,
+    },
+    /// Sends `dex_fee` to the configured dex fee account, then makes `payment`, a
+    /// `LamportsPayment` or an `SPLTokenPayment`, so that neither can happen without the other.
+    PaymentWithDexFee {
+        dex_fee: u64,
+        payment: Box<AtomicSwapInstruction>,
+    },
+    /// Creates the config account, signed by the program's upgrade authority and the admin.
+    InitializeConfig {
+        admin: Pubkey,
+        settings: ConfigSettings,
+    },
+    /// Replaces the config settings, signed by the config admin.
+    UpdateConfig { settings: ConfigSettings },
+    /// Proposes another config admin, signed by the current one. The current admin keeps the
+    /// role until the proposed one accepts it, and can propose another one meanwhile.
+    ProposeConfigAdmin { admin: Pubkey },
+    /// Pauses or resumes new payments, signed by the config admin. Spends and refunds of existing
+    /// payments are never paused.
+    SetPaused { paused: bool },
+    /// Makes the proposed config admin the admin, signed by it.
+    AcceptConfigAdmin,
 }
 pub struct LamportsPaymentParams {
     pub secret_hash: [u8; 32],
+    pub secret_hash_algorithm: SecretHashAlgorithm,
     pub lock_time: u64,
+    pub lock_time_kind: LockTimeKind,
+    pub spend_deadline: u64,
     pub amount: u64,
+    pub relayer_fee: u64,
+    pub watcher_reward: u64,
     pub receiver: Pubkey,
-    pub rent_exemption_lamports: u64,
     pub vault_bump_seed: u8,
     pub vault_bump_seed_data: u8,
+    /// Paid ahead of the payment by `PaymentWithDexFee`, zero otherwise.
+    pub dex_fee: u64,
 }
 pub struct SPLTokenPaymentParams {
     pub secret_hash: [u8; 32],
+    pub secret_hash_algorithm: SecretHashAlgorithm,
     pub lock_time: u64,
+    pub lock_time_kind: LockTimeKind,
+    pub spend_deadline: u64,
     pub amount: u64,
+    pub relayer_fee: u64,
+    pub watcher_reward: u64,
     pub receiver: Pubkey,
     pub token_program: Pubkey,
-    pub rent_exemption_lamports: u64,
     pub vault_bump_seed: u8,
     pub vault_bump_seed_data: u8,
+    /// Paid ahead of the payment by `PaymentWithDexFee`, zero otherwise.
+    pub dex_fee: u64,
 }
 pub struct ReceiverSpendParams {
-    pub secret: [u8; 32],
+    pub secret: Vec<u8>,
     pub lock_time: u64,
     pub amount: u64,
     pub sender: Pubkey,
@@ -82,223 +172,334 @@ pub struct SenderRefundParams {
     pub vault_bump_seed: u8,
     pub vault_bump_seed_data: u8,
 }
-impl AtomicSwapInstruction {
-    pub fn unpack(input: &[u8]) -> Result<AtomicSwapInstruction, ProgramError> {
-        msg!("input length: {}", input.len());
-        match input[0] {
-            0 => {
-                if input.len() != 91 {
-                    return Err(ProgramError::Custom(INVALID_INPUT_LENGTH));
-                }
+pub struct QueryPaymentParams {
+    pub secret_hash: [u8; 32],
+    pub lock_time: u64,
+    pub amount: u64,
+    pub sender: Pubkey,
+    pub receiver: Pubkey,
+    pub mint: Pubkey,
+    pub token_program: Pubkey,
+}
+pub struct ClosePaymentParams {
+    pub secret_hash: [u8; 32],
+    pub lock_time: u64,
+    pub amount: u64,
+    pub receiver: Pubkey,
+    pub token_program: Pubkey,
+    pub vault_bump_seed: u8,
+    pub vault_bump_seed_data: u8,
+}
+/// First byte of versioned instruction data, laid out as `[VERSIONED_INSTRUCTION, version, tag,
+/// fields..]`. The unversioned v1 layouts start with their tag, which never takes this value.
+pub const VERSIONED_INSTRUCTION: u8 = 0xff;
+pub const INSTRUCTION_VERSION: u8 = 2;
+/// Longest secret accepted by `ReceiverSpend`.
+pub const MAX_SECRET_LEN: usize = 64;
 
-                let secret_hash = input[1..33]
-                    .try_into()
-                    .map_err(|_| ProgramError::Custom(INVALID_SECRET_HASH))?;
+const LAMPORTS_PAYMENT: u8 = 0;
+const SPL_TOKEN_PAYMENT: u8 = 1;
+const RECEIVER_SPEND: u8 = 2;
+const SENDER_REFUND: u8 = 3;
+const CLOSE_PAYMENT: u8 = 4;
+const COMPACT_RECEIVER_SPEND: u8 = 5;
+const COMPACT_SENDER_REFUND: u8 = 6;
+const QUERY_PAYMENT: u8 = 7;
+const RELAYED_RECEIVER_SPEND: u8 = 8;
+const RELAYED_SENDER_REFUND: u8 = 9;
+const PAYMENT_WITH_DEX_FEE: u8 = 10;
+const INITIALIZE_CONFIG: u8 = 11;
+const UPDATE_CONFIG: u8 = 12;
+const PROPOSE_CONFIG_ADMIN: u8 = 13;
+const SET_PAUSED: u8 = 14;
+const ACCEPT_CONFIG_ADMIN: u8 = 15;
 
-                let lock_time_array = input[33..41]
-                    .try_into()
-                    .map_err(|_| ProgramError::Custom(INVALID_LOCK_TIME))?;
-                let lock_time = u64::from_le_bytes(lock_time_array);
+struct Reader<'a> {
+    input: &'a [u8],
+}
 
-                let amount_array = input[41..49]
-                    .try_into()
-                    .map_err(|_| ProgramError::Custom(INVALID_AMOUNT))?;
-                let amount = u64::from_le_bytes(amount_array);
+impl<'a> Reader<'a> {
+    fn read_bytes(&mut self, len: usize) -> Result<&'a [u8], SwapError> {
+        if self.input.len() < len {
+            return Err(SwapError::InvalidInputLength);
+        }
+        let (bytes, rest) = self.input.split_at(len);
+        self.input = rest;
+        Ok(bytes)
+    }
 
-                let receiver = Pubkey::new_from_array(
-                    input[49..81]
-                        .try_into()
-                        .map_err(|_| ProgramError::Custom(INVALID_RECEIVER_PUBKEY))?,
-                );
+    fn read_array<const N: usize>(&mut self) -> Result<[u8; N], SwapError> {
+        self.read_bytes(N)?
+            .try_into()
+            .map_err(|_| SwapError::InvalidInputLength)
+    }
 
-                let rent_exemption_lamports_array = input[81..89]
-                    .try_into()
-                    .map_err(|_| ProgramError::Custom(INVALID_AMOUNT))?;
-                let rent_exemption_lamports = u64::from_le_bytes(rent_exemption_lamports_array);
+    fn read_u8(&mut self) -> Result<u8, SwapError> {
+        self.read_array::<1>().map(|[byte]| byte)
+    }
 
-                Ok(AtomicSwapInstruction::LamportsPayment {
-                    secret_hash,
-                    lock_time,
-                    amount,
-                    receiver,
-                    rent_exemption_lamports,
-                    vault_bump_seed: input[89],
-                    vault_bump_seed_data: input[90],
-                })
-            }
-            1 => {
-                if input.len() != 123 {
-                    return Err(ProgramError::Custom(INVALID_INPUT_LENGTH));
+    fn read_u16(&mut self) -> Result<u16, SwapError> {
+        self.read_array().map(u16::from_le_bytes)
+    }
+
+    fn read_u64(&mut self) -> Result<u64, SwapError> {
+        self.read_array().map(u64::from_le_bytes)
+    }
+
+    fn read_pubkey(&mut self) -> Result<Pubkey, SwapError> {
+        self.read_array().map(Pubkey::new_from_array)
+    }
+
+    fn finish(&self) -> Result<(), SwapError> {
+        if !self.input.is_empty() {
+            return Err(SwapError::InvalidInputLength);
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Layout {
+    /// v1 payments carrying the ignored `rent_exemption_lamports`.
+    V1WithRent,
+    V1,
+    /// The [`INSTRUCTION_VERSION`] layout, the only one carrying the fields added since v1 and the
+    /// instructions added since.
+    Versioned,
+}
+
+impl AtomicSwapInstruction {
+    pub fn unpack(input: &[u8]) -> Result<AtomicSwapInstruction, ProgramError> {
+        let mut reader = Reader { input };
+        let instruction = match reader.read_u8()? {
+            VERSIONED_INSTRUCTION => {
+                if reader.read_u8()? != INSTRUCTION_VERSION {
+                    return Err(SwapError::UnsupportedInstructionVersion.into());
                 }
+                let tag = reader.read_u8()?;
+                Self::unpack_fields(tag, &mut reader, Layout::Versioned)?
+            }
+            // v1 layouts. Tags 0 and 1 carry a client supplied `rent_exemption_lamports`, which is
+            // ignored in favour of the `Rent` sysvar. Payments without it are only sent versioned.
+            tag @ (LAMPORTS_PAYMENT | SPL_TOKEN_PAYMENT) => {
+                Self::unpack_fields(tag, &mut reader, Layout::V1WithRent)?
+            }
+            tag @ (RECEIVER_SPEND | SENDER_REFUND | CLOSE_PAYMENT) => {
+                Self::unpack_fields(tag, &mut reader, Layout::V1)?
+            }
+            _ => return Err(SwapError::InvalidAtomicSwapInstruction.into()),
+        };
+        reader.finish()?;
+        Ok(instruction)
+    }
 
-                let secret_hash = input[1..33]
-                    .try_into()
-                    .map_err(|_| ProgramError::Custom(INVALID_SECRET_HASH))?;
-
-                let lock_time_array = input[33..41]
-                    .try_into()
-                    .map_err(|_| ProgramError::Custom(INVALID_LOCK_TIME))?;
-                let lock_time = u64::from_le_bytes(lock_time_array);
-
-                let amount_array = input[41..49]
-                    .try_into()
-                    .map_err(|_| ProgramError::Custom(INVALID_AMOUNT))?;
-                let amount = u64::from_le_bytes(amount_array);
-
-                let receiver = Pubkey::new_from_array(
-                    input[49..81]
-                        .try_into()
-                        .map_err(|_| ProgramError::Custom(INVALID_RECEIVER_PUBKEY))?,
-                );
-
-                let token_program = Pubkey::new_from_array(
-                    input[81..113]
-                        .try_into()
-                        .map_err(|_| ProgramError::Custom(INVALID_TOKEN_PROGRAM))?,
-                );
-
-                let rent_exemption_lamports_array = input[113..121]
-                    .try_into()
-                    .map_err(|_| ProgramError::Custom(INVALID_AMOUNT))?;
-                let rent_exemption_lamports = u64::from_le_bytes(rent_exemption_lamports_array);
-
-                Ok(AtomicSwapInstruction::SPLTokenPayment {
+    fn unpack_fields(
+        tag: u8,
+        reader: &mut Reader,
+        layout: Layout,
+    ) -> Result<AtomicSwapInstruction, SwapError> {
+        let instruction = match tag {
+            LAMPORTS_PAYMENT => {
+                let secret_hash = reader.read_array()?;
+                let lock_time = reader.read_u64()?;
+                let amount = reader.read_u64()?;
+                let receiver = reader.read_pubkey()?;
+                if layout == Layout::V1WithRent {
+                    reader.read_u64()?;
+                }
+                AtomicSwapInstruction::LamportsPayment {
                     secret_hash,
                     lock_time,
                     amount,
                     receiver,
-                    token_program,
-                    rent_exemption_lamports,
-                    vault_bump_seed: input[121],
-                    vault_bump_seed_data: input[122],
-                })
-            }
-            2 => {
-                if input.len() != 115 {
-                    return Err(ProgramError::Custom(INVALID_INPUT_LENGTH));
+                    vault_bump_seed: reader.read_u8()?,
+                    vault_bump_seed_data: reader.read_u8()?,
+                    secret_hash_algorithm: read_secret_hash_algorithm(reader, layout)?,
+                    lock_time_kind: read_lock_time_kind(reader, layout)?,
+                    spend_deadline: read_versioned(reader, layout, Reader::read_u64)?
+                        .unwrap_or(lock_time),
+                    relayer_fee: read_versioned(reader, layout, Reader::read_u64)?.unwrap_or(0),
+                    watcher_reward: read_versioned(reader, layout, Reader::read_u64)?.unwrap_or(0),
                 }
-
-                let secret = input[1..33]
-                    .try_into()
-                    .map_err(|_| ProgramError::Custom(INVALID_SECRET))?;
-
-                let lock_time_array = input[33..41]
-                    .try_into()
-                    .map_err(|_| ProgramError::Custom(INVALID_LOCK_TIME))?;
-                let lock_time = u64::from_le_bytes(lock_time_array);
-
-                let amount_array = input[41..49]
-                    .try_into()
-                    .map_err(|_| ProgramError::Custom(INVALID_AMOUNT))?;
-                let amount = u64::from_le_bytes(amount_array);
-
-                let sender = Pubkey::new_from_array(
-                    input[49..81]
-                        .try_into()
-                        .map_err(|_| ProgramError::Custom(INVALID_SENDER_PUBKEY))?,
-                );
-
-                let token_program = Pubkey::new_from_array(
-                    input[81..113]
-                        .try_into()
-                        .map_err(|_| ProgramError::Custom(INVALID_TOKEN_PROGRAM))?,
-                );
-
-                Ok(AtomicSwapInstruction::ReceiverSpend {
-                    secret,
-                    lock_time,
-                    amount,
-                    sender,
-                    token_program,
-                    vault_bump_seed: input[113],
-                    vault_bump_seed_data: input[114],
-                })
             }
-            3 => {
-                if input.len() != 115 {
-                    return Err(ProgramError::Custom(INVALID_INPUT_LENGTH));
+            SPL_TOKEN_PAYMENT => {
+                let secret_hash = reader.read_array()?;
+                let lock_time = reader.read_u64()?;
+                let amount = reader.read_u64()?;
+                let receiver = reader.read_pubkey()?;
+                let token_program = reader.read_pubkey()?;
+                if layout == Layout::V1WithRent {
+                    reader.read_u64()?;
                 }
-
-                let secret_hash = input[1..33]
-                    .try_into()
-                    .map_err(|_| ProgramError::Custom(INVALID_SECRET_HASH))?;
-
-                let lock_time_array = input[33..41]
-                    .try_into()
-                    .map_err(|_| ProgramError::Custom(INVALID_LOCK_TIME))?;
-                let lock_time = u64::from_le_bytes(lock_time_array);
-
-                let amount_array = input[41..49]
-                    .try_into()
-                    .map_err(|_| ProgramError::Custom(INVALID_AMOUNT))?;
-                let amount = u64::from_le_bytes(amount_array);
-
-                let receiver = Pubkey::new_from_array(
-                    input[49..81]
-                        .try_into()
-                        .map_err(|_| ProgramError::Custom(INVALID_RECEIVER_PUBKEY))?,
-                );
-
-                let token_program = Pubkey::new_from_array(
-                    input[81..113]
-                        .try_into()
-                        .map_err(|_| ProgramError::Custom(INVALID_TOKEN_PROGRAM))?,
-                );
-
-                Ok(AtomicSwapInstruction::SenderRefund {
+                AtomicSwapInstruction::SPLTokenPayment {
                     secret_hash,
                     lock_time,
                     amount,
                     receiver,
                     token_program,
-                    vault_bump_seed: input[113],
-                    vault_bump_seed_data: input[114],
-                })
+                    vault_bump_seed: reader.read_u8()?,
+                    vault_bump_seed_data: reader.read_u8()?,
+                    secret_hash_algorithm: read_secret_hash_algorithm(reader, layout)?,
+                    lock_time_kind: read_lock_time_kind(reader, layout)?,
+                    spend_deadline: read_versioned(reader, layout, Reader::read_u64)?
+                        .unwrap_or(lock_time),
+                    relayer_fee: read_versioned(reader, layout, Reader::read_u64)?.unwrap_or(0),
+                    watcher_reward: read_versioned(reader, layout, Reader::read_u64)?.unwrap_or(0),
+                }
             }
-            _ => Err(ProgramError::Custom(INVALID_ATOMIC_SWAP_INSTRUCTION)),
-        }
+            RECEIVER_SPEND => AtomicSwapInstruction::ReceiverSpend {
+                secret: read_secret(reader, layout)?,
+                lock_time: reader.read_u64()?,
+                amount: reader.read_u64()?,
+                sender: reader.read_pubkey()?,
+                token_program: reader.read_pubkey()?,
+                vault_bump_seed: reader.read_u8()?,
+                vault_bump_seed_data: reader.read_u8()?,
+            },
+            RELAYED_RECEIVER_SPEND => AtomicSwapInstruction::RelayedReceiverSpend {
+                secret: read_secret(reader, layout)?,
+                lock_time: reader.read_u64()?,
+                amount: reader.read_u64()?,
+                sender: reader.read_pubkey()?,
+                token_program: reader.read_pubkey()?,
+                vault_bump_seed: reader.read_u8()?,
+                vault_bump_seed_data: reader.read_u8()?,
+            },
+            SENDER_REFUND => AtomicSwapInstruction::SenderRefund {
+                secret_hash: reader.read_array()?,
+                lock_time: reader.read_u64()?,
+                amount: reader.read_u64()?,
+                receiver: reader.read_pubkey()?,
+                token_program: reader.read_pubkey()?,
+                vault_bump_seed: reader.read_u8()?,
+                vault_bump_seed_data: reader.read_u8()?,
+            },
+            RELAYED_SENDER_REFUND => AtomicSwapInstruction::RelayedSenderRefund {
+                secret_hash: reader.read_array()?,
+                lock_time: reader.read_u64()?,
+                amount: reader.read_u64()?,
+                receiver: reader.read_pubkey()?,
+                token_program: reader.read_pubkey()?,
+                vault_bump_seed: reader.read_u8()?,
+                vault_bump_seed_data: reader.read_u8()?,
+            },
+            CLOSE_PAYMENT => AtomicSwapInstruction::ClosePayment {
+                secret_hash: reader.read_array()?,
+                lock_time: reader.read_u64()?,
+                amount: reader.read_u64()?,
+                receiver: reader.read_pubkey()?,
+                token_program: reader.read_pubkey()?,
+                vault_bump_seed: reader.read_u8()?,
+                vault_bump_seed_data: reader.read_u8()?,
+            },
+            COMPACT_RECEIVER_SPEND => AtomicSwapInstruction::CompactReceiverSpend {
+                secret: read_secret(reader, layout)?,
+            },
+            COMPACT_SENDER_REFUND => AtomicSwapInstruction::CompactSenderRefund,
+            QUERY_PAYMENT => AtomicSwapInstruction::QueryPayment {
+                secret_hash: reader.read_array()?,
+                lock_time: reader.read_u64()?,
+                amount: reader.read_u64()?,
+                sender: reader.read_pubkey()?,
+                receiver: reader.read_pubkey()?,
+                mint: reader.read_pubkey()?,
+                token_program: reader.read_pubkey()?,
+            },
+            PAYMENT_WITH_DEX_FEE => {
+                let dex_fee = reader.read_u64()?;
+                let payment = match reader.read_u8()? {
+                    tag @ (LAMPORTS_PAYMENT | SPL_TOKEN_PAYMENT) => {
+                        Self::unpack_fields(tag, reader, layout)?
+                    }
+                    _ => return Err(SwapError::InvalidAtomicSwapInstruction),
+                };
+                AtomicSwapInstruction::PaymentWithDexFee {
+                    dex_fee,
+                    payment: Box::new(payment),
+                }
+            }
+            INITIALIZE_CONFIG => AtomicSwapInstruction::InitializeConfig {
+                admin: reader.read_pubkey()?,
+                settings: read_config_settings(reader)?,
+            },
+            UPDATE_CONFIG => AtomicSwapInstruction::UpdateConfig {
+                settings: read_config_settings(reader)?,
+            },
+            PROPOSE_CONFIG_ADMIN => AtomicSwapInstruction::ProposeConfigAdmin {
+                admin: reader.read_pubkey()?,
+            },
+            SET_PAUSED => AtomicSwapInstruction::SetPaused {
+                paused: match reader.read_u8()? {
+                    0 => false,
+                    1 => true,
+                    _ => return Err(SwapError::InvalidAtomicSwapInstruction),
+                },
+            },
+            ACCEPT_CONFIG_ADMIN => AtomicSwapInstruction::AcceptConfigAdmin,
+            _ => return Err(SwapError::InvalidAtomicSwapInstruction),
+        };
+        Ok(instruction)
     }
-    #[allow(dead_code)]
+
+    /// # Panics
+    ///
+    /// If a secret is longer than 255 bytes, which the builders never produce.
     pub fn pack(&self) -> Vec<u8> {
-        let mut buf = Vec::new();
+        let mut buf = vec![VERSIONED_INSTRUCTION, INSTRUCTION_VERSION];
         match *self {
             AtomicSwapInstruction::LamportsPayment {
                 ref secret_hash,
+                secret_hash_algorithm,
                 lock_time,
+                lock_time_kind,
+                spend_deadline,
                 amount,
+                relayer_fee,
+                watcher_reward,
                 ref receiver,
-                rent_exemption_lamports,
                 vault_bump_seed,
                 vault_bump_seed_data,
             } => {
-                buf.push(0);
+                buf.push(LAMPORTS_PAYMENT);
                 buf.extend_from_slice(secret_hash);
                 buf.extend_from_slice(&lock_time.to_le_bytes());
                 buf.extend_from_slice(&amount.to_le_bytes());
                 buf.extend_from_slice(&receiver.to_bytes());
-                buf.extend_from_slice(&rent_exemption_lamports.to_le_bytes());
                 buf.push(vault_bump_seed);
                 buf.push(vault_bump_seed_data);
+                buf.push(secret_hash_algorithm.to_u8());
+                buf.push(lock_time_kind.to_u8());
+                buf.extend_from_slice(&spend_deadline.to_le_bytes());
+                buf.extend_from_slice(&relayer_fee.to_le_bytes());
+                buf.extend_from_slice(&watcher_reward.to_le_bytes());
             }
             AtomicSwapInstruction::SPLTokenPayment {
                 ref secret_hash,
+                secret_hash_algorithm,
                 lock_time,
+                lock_time_kind,
+                spend_deadline,
                 amount,
+                relayer_fee,
+                watcher_reward,
                 ref receiver,
                 ref token_program,
-                rent_exemption_lamports,
                 vault_bump_seed,
                 vault_bump_seed_data,
             } => {
-                buf.push(1);
+                buf.push(SPL_TOKEN_PAYMENT);
                 buf.extend_from_slice(secret_hash);
                 buf.extend_from_slice(&lock_time.to_le_bytes());
                 buf.extend_from_slice(&amount.to_le_bytes());
                 buf.extend_from_slice(&receiver.to_bytes());
                 buf.extend_from_slice(&token_program.to_bytes());
-                buf.extend_from_slice(&rent_exemption_lamports.to_le_bytes());
                 buf.push(vault_bump_seed);
                 buf.push(vault_bump_seed_data);
+                buf.push(secret_hash_algorithm.to_u8());
+                buf.push(lock_time_kind.to_u8());
+                buf.extend_from_slice(&spend_deadline.to_le_bytes());
+                buf.extend_from_slice(&relayer_fee.to_le_bytes());
+                buf.extend_from_slice(&watcher_reward.to_le_bytes());
             }
             AtomicSwapInstruction::ReceiverSpend {
                 ref secret,
@@ -309,8 +510,26 @@ impl AtomicSwapInstruction {
                 vault_bump_seed,
                 vault_bump_seed_data,
             } => {
-                buf.push(2);
-                buf.extend_from_slice(secret);
+                buf.push(RECEIVER_SPEND);
+                pack_secret(&mut buf, secret);
+                buf.extend_from_slice(&lock_time.to_le_bytes());
+                buf.extend_from_slice(&amount.to_le_bytes());
+                buf.extend_from_slice(&sender.to_bytes());
+                buf.extend_from_slice(&token_program.to_bytes());
+                buf.push(vault_bump_seed);
+                buf.push(vault_bump_seed_data);
+            }
+            AtomicSwapInstruction::RelayedReceiverSpend {
+                ref secret,
+                lock_time,
+                amount,
+                ref sender,
+                ref token_program,
+                vault_bump_seed,
+                vault_bump_seed_data,
+            } => {
+                buf.push(RELAYED_RECEIVER_SPEND);
+                pack_secret(&mut buf, secret);
                 buf.extend_from_slice(&lock_time.to_le_bytes());
                 buf.extend_from_slice(&amount.to_le_bytes());
                 buf.extend_from_slice(&sender.to_bytes());
@@ -327,7 +546,43 @@ impl AtomicSwapInstruction {
                 vault_bump_seed,
                 vault_bump_seed_data,
             } => {
-                buf.push(3);
+                buf.push(SENDER_REFUND);
+                buf.extend_from_slice(secret_hash);
+                buf.extend_from_slice(&lock_time.to_le_bytes());
+                buf.extend_from_slice(&amount.to_le_bytes());
+                buf.extend_from_slice(&receiver.to_bytes());
+                buf.extend_from_slice(&token_program.to_bytes());
+                buf.push(vault_bump_seed);
+                buf.push(vault_bump_seed_data);
+            }
+            AtomicSwapInstruction::RelayedSenderRefund {
+                ref secret_hash,
+                lock_time,
+                amount,
+                ref receiver,
+                ref token_program,
+                vault_bump_seed,
+                vault_bump_seed_data,
+            } => {
+                buf.push(RELAYED_SENDER_REFUND);
+                buf.extend_from_slice(secret_hash);
+                buf.extend_from_slice(&lock_time.to_le_bytes());
+                buf.extend_from_slice(&amount.to_le_bytes());
+                buf.extend_from_slice(&receiver.to_bytes());
+                buf.extend_from_slice(&token_program.to_bytes());
+                buf.push(vault_bump_seed);
+                buf.push(vault_bump_seed_data);
+            }
+            AtomicSwapInstruction::ClosePayment {
+                ref secret_hash,
+                lock_time,
+                amount,
+                ref receiver,
+                ref token_program,
+                vault_bump_seed,
+                vault_bump_seed_data,
+            } => {
+                buf.push(CLOSE_PAYMENT);
                 buf.extend_from_slice(secret_hash);
                 buf.extend_from_slice(&lock_time.to_le_bytes());
                 buf.extend_from_slice(&amount.to_le_bytes());
@@ -336,7 +591,677 @@ impl AtomicSwapInstruction {
                 buf.push(vault_bump_seed);
                 buf.push(vault_bump_seed_data);
             }
+            AtomicSwapInstruction::CompactReceiverSpend { ref secret } => {
+                buf.push(COMPACT_RECEIVER_SPEND);
+                pack_secret(&mut buf, secret);
+            }
+            AtomicSwapInstruction::CompactSenderRefund => buf.push(COMPACT_SENDER_REFUND),
+            AtomicSwapInstruction::QueryPayment {
+                ref secret_hash,
+                lock_time,
+                amount,
+                ref sender,
+                ref receiver,
+                ref mint,
+                ref token_program,
+            } => {
+                buf.push(QUERY_PAYMENT);
+                buf.extend_from_slice(secret_hash);
+                buf.extend_from_slice(&lock_time.to_le_bytes());
+                buf.extend_from_slice(&amount.to_le_bytes());
+                buf.extend_from_slice(&sender.to_bytes());
+                buf.extend_from_slice(&receiver.to_bytes());
+                buf.extend_from_slice(&mint.to_bytes());
+                buf.extend_from_slice(&token_program.to_bytes());
+            }
+            AtomicSwapInstruction::PaymentWithDexFee {
+                dex_fee,
+                ref payment,
+            } => {
+                buf.push(PAYMENT_WITH_DEX_FEE);
+                buf.extend_from_slice(&dex_fee.to_le_bytes());
+                // The payment's own tag and fields, without repeating the version.
+                buf.extend_from_slice(&payment.pack()[2..]);
+            }
+            AtomicSwapInstruction::InitializeConfig {
+                ref admin,
+                ref settings,
+            } => {
+                buf.push(INITIALIZE_CONFIG);
+                buf.extend_from_slice(&admin.to_bytes());
+                settings.pack_into(&mut buf);
+            }
+            AtomicSwapInstruction::UpdateConfig { ref settings } => {
+                buf.push(UPDATE_CONFIG);
+                settings.pack_into(&mut buf);
+            }
+            AtomicSwapInstruction::ProposeConfigAdmin { ref admin } => {
+                buf.push(PROPOSE_CONFIG_ADMIN);
+                buf.extend_from_slice(&admin.to_bytes());
+            }
+            AtomicSwapInstruction::SetPaused { paused } => {
+                buf.push(SET_PAUSED);
+                buf.push(paused as u8);
+            }
+            AtomicSwapInstruction::AcceptConfigAdmin => buf.push(ACCEPT_CONFIG_ADMIN),
         }
         buf
     }
 }
+
+fn pack_secret(buf: &mut Vec<u8>, secret: &[u8]) {
+    buf.push(u8::try_from(secret.len()).expect("secret length fits its prefix"));
+    buf.extend_from_slice(secret);
+}
+
+/// Whether `secret` can be revealed by a spend, it is checked again on-chain.
+fn is_valid_secret(secret: &[u8]) -> bool {
+    (1..=MAX_SECRET_LEN).contains(&secret.len())
+}
+
+fn read_secret(reader: &mut Reader, layout: Layout) -> Result<Vec<u8>, SwapError> {
+    if layout != Layout::Versioned {
+        return reader.read_array::<32>().map(Vec::from);
+    }
+    let len = reader.read_u8()? as usize;
+    if len > MAX_SECRET_LEN {
+        return Err(SwapError::SecretTooLong);
+    }
+    if len == 0 {
+        return Err(SwapError::InvalidSecret);
+    }
+    reader.read_bytes(len).map(<[u8]>::to_vec)
+}
+
+/// Reads a field only the versioned layout carries, `None` for the v1 layouts.
+fn read_versioned<'a, T>(
+    reader: &mut Reader<'a>,
+    layout: Layout,
+    read: impl FnOnce(&mut Reader<'a>) -> Result<T, SwapError>,
+) -> Result<Option<T>, SwapError> {
+    if layout != Layout::Versioned {
+        return Ok(None);
+    }
+    read(reader).map(Some)
+}
+
+fn read_secret_hash_algorithm(
+    reader: &mut Reader,
+    layout: Layout,
+) -> Result<SecretHashAlgorithm, SwapError> {
+    match read_versioned(reader, layout, Reader::read_u8)? {
+        Some(byte) => {
+            SecretHashAlgorithm::from_u8(byte).ok_or(SwapError::InvalidSecretHashAlgorithm)
+        }
+        None => Ok(SecretHashAlgorithm::Sha256),
+    }
+}
+
+fn read_config_settings(reader: &mut Reader) -> Result<ConfigSettings, SwapError> {
+    let dex_fee_account = reader.read_pubkey()?;
+    let dex_fee_rate = reader.read_u16()?;
+    let min_amount = reader.read_u64()?;
+    let count = reader.read_u8()? as usize;
+    if count > MAX_TOKEN_PROGRAMS {
+        return Err(SwapError::InvalidConfig);
+    }
+    Ok(ConfigSettings {
+        dex_fee_account,
+        dex_fee_rate,
+        min_amount,
+        allowed_token_programs: (0..count)
+            .map(|_| reader.read_pubkey())
+            .collect::<Result<_, _>>()?,
+    })
+}
+
+fn read_lock_time_kind(reader: &mut Reader, layout: Layout) -> Result<LockTimeKind, SwapError> {
+    match read_versioned(reader, layout, Reader::read_u8)? {
+        Some(byte) => LockTimeKind::from_u8(byte).ok_or(SwapError::InvalidLockTime),
+        None => Ok(LockTimeKind::UnixTimestamp),
+    }
+}
+
+/// Token accounts of an SPL token swap, `user_token_account` belonging to the signer of the
+/// instruction and `vault_token_account` to the vault PDA.
+#[derive(Clone, Copy, Debug)]
+pub struct SplTokenAccounts {
+    pub mint: Pubkey,
+    pub token_program: Pubkey,
+    pub user_token_account: Pubkey,
+    pub vault_token_account: Pubkey,
+}
+
+impl SplTokenAccounts {
+    fn account_metas(&self, mint_writable: bool) -> [AccountMeta; 4] {
+        [
+            AccountMeta::new(self.user_token_account, false),
+            AccountMeta::new(self.vault_token_account, false),
+            if mint_writable {
+                AccountMeta::new(self.mint, false)
+            } else {
+                AccountMeta::new_readonly(self.mint, false)
+            },
+            AccountMeta::new_readonly(self.token_program, false),
+        ]
+    }
+}
+
+fn swap_instruction(
+    program_id: &Pubkey,
+    user: &Pubkey,
+    payment_seeds: &PaymentSeeds,
+    token_accounts: Option<&SplTokenAccounts>,
+    mint_writable: bool,
+    build: impl FnOnce(u8, u8) -> AtomicSwapInstruction,
+) -> Instruction {
+    let ((vault_pda, vault_bump_seed), (vault_pda_data, vault_bump_seed_data)) =
+        payment_seeds.find_vault_addresses(program_id);
+    let mut accounts = vec![
+        AccountMeta::new(*user, true),
+        AccountMeta::new(vault_pda_data, false),
+        AccountMeta::new(vault_pda, false),
+    ];
+    if let Some(token_accounts) = token_accounts {
+        accounts.extend(token_accounts.account_metas(mint_writable));
+    }
+    accounts.push(AccountMeta::new_readonly(system_program::id(), false));
+    Instruction {
+        program_id: *program_id,
+        accounts,
+        data: build(vault_bump_seed, vault_bump_seed_data).pack(),
+    }
+}
+
+/// Payments read the config account, which follows the system program.
+fn with_config_account(program_id: &Pubkey, mut instruction: Instruction) -> Instruction {
+    let (config, _) = find_config_address(program_id);
+    instruction
+        .accounts
+        .push(AccountMeta::new_readonly(config, false));
+    instruction
+}
+
+fn token_program(token_accounts: Option<&SplTokenAccounts>) -> Pubkey {
+    token_accounts.map_or(Pubkey::default(), |accounts| accounts.token_program)
+}
+
+/// Terms of a new payment made by [`lamports_payment`] or [`spl_token_payment`].
+#[derive(Clone, Copy, Debug)]
+pub struct NewPayment {
+    pub receiver: Pubkey,
+    pub secret_hash: [u8; 32],
+    pub secret_hash_algorithm: SecretHashAlgorithm,
+    pub lock_time: u64,
+    pub lock_time_kind: LockTimeKind,
+    /// Defaults to `lock_time`, see `Payment::spend_deadline`.
+    pub spend_deadline: Option<u64>,
+    pub amount: u64,
+    pub relayer_fee: u64,
+    pub watcher_reward: u64,
+}
+
+pub fn lamports_payment(program_id: &Pubkey, sender: &Pubkey, payment: &NewPayment) -> Instruction {
+    let payment_seeds = PaymentSeeds::new(
+        payment.lock_time,
+        &payment.secret_hash,
+        sender,
+        &payment.receiver,
+        None,
+    );
+    let instruction = swap_instruction(
+        program_id,
+        sender,
+        &payment_seeds,
+        None,
+        false,
+        |vault_bump_seed, vault_bump_seed_data| AtomicSwapInstruction::LamportsPayment {
+            secret_hash: payment.secret_hash,
+            secret_hash_algorithm: payment.secret_hash_algorithm,
+            lock_time: payment.lock_time,
+            lock_time_kind: payment.lock_time_kind,
+            spend_deadline: payment.spend_deadline.unwrap_or(payment.lock_time),
+            amount: payment.amount,
+            relayer_fee: payment.relayer_fee,
+            watcher_reward: payment.watcher_reward,
+            receiver: payment.receiver,
+            vault_bump_seed,
+            vault_bump_seed_data,
+        },
+    );
+    with_config_account(program_id, instruction)
+}
+
+pub fn spl_token_payment(
+    program_id: &Pubkey,
+    sender: &Pubkey,
+    payment: &NewPayment,
+    token_accounts: &SplTokenAccounts,
+) -> Instruction {
+    let payment_seeds = PaymentSeeds::new(
+        payment.lock_time,
+        &payment.secret_hash,
+        sender,
+        &payment.receiver,
+        Some(&token_accounts.mint),
+    );
+    let instruction = swap_instruction(
+        program_id,
+        sender,
+        &payment_seeds,
+        Some(token_accounts),
+        false,
+        |vault_bump_seed, vault_bump_seed_data| AtomicSwapInstruction::SPLTokenPayment {
+            secret_hash: payment.secret_hash,
+            secret_hash_algorithm: payment.secret_hash_algorithm,
+            lock_time: payment.lock_time,
+            lock_time_kind: payment.lock_time_kind,
+            spend_deadline: payment.spend_deadline.unwrap_or(payment.lock_time),
+            amount: payment.amount,
+            relayer_fee: payment.relayer_fee,
+            watcher_reward: payment.watcher_reward,
+            receiver: payment.receiver,
+            token_program: token_accounts.token_program,
+            vault_bump_seed,
+            vault_bump_seed_data,
+        },
+    );
+    with_config_account(program_id, instruction)
+}
+
+/// `amount` is the amount held by the vault, which is lower than the paid amount for mints
+/// charging a transfer fee. `secret_hash_algorithm` is the one the payment was created with,
+/// `None` when `secret` is empty or longer than [`MAX_SECRET_LEN`] bytes.
+#[allow(clippy::too_many_arguments)]
+pub fn receiver_spend(
+    program_id: &Pubkey,
+    receiver: &Pubkey,
+    sender: &Pubkey,
+    secret: &[u8],
+    secret_hash_algorithm: SecretHashAlgorithm,
+    lock_time: u64,
+    amount: u64,
+    token_accounts: Option<&SplTokenAccounts>,
+) -> Option<Instruction> {
+    if !is_valid_secret(secret) {
+        return None;
+    }
+    let secret_hash = secret_hash_algorithm.hash_secret(secret);
+    let mint = token_accounts.map(|accounts| &accounts.mint);
+    let payment_seeds = PaymentSeeds::new(lock_time, &secret_hash, sender, receiver, mint);
+    Some(swap_instruction(
+        program_id,
+        receiver,
+        &payment_seeds,
+        token_accounts,
+        false,
+        |vault_bump_seed, vault_bump_seed_data| AtomicSwapInstruction::ReceiverSpend {
+            secret: secret.to_vec(),
+            lock_time,
+            amount,
+            sender: *sender,
+            token_program: token_program(token_accounts),
+            vault_bump_seed,
+            vault_bump_seed_data,
+        },
+    ))
+}
+
+/// Makes `payment`, built by [`lamports_payment`] or [`spl_token_payment`], send `dex_fee` to
+/// `dex_fee_account` first. That is the configured dex fee account for lamports payments and a
+/// token account it owns for SPL token payments, `dex_fee` being at least the configured rate of
+/// the amount. `None` when `payment` is not a payment instruction.
+pub fn payment_with_dex_fee(
+    mut payment: Instruction,
+    dex_fee: u64,
+    dex_fee_account: &Pubkey,
+) -> Option<Instruction> {
+    let swap_instruction =
+        AtomicSwapInstruction::unpack(&payment.data)
+            .ok()
+            .filter(|instruction| {
+                matches!(
+                    instruction,
+                    AtomicSwapInstruction::LamportsPayment { .. }
+                        | AtomicSwapInstruction::SPLTokenPayment { .. }
+                )
+            })?;
+    payment.data = AtomicSwapInstruction::PaymentWithDexFee {
+        dex_fee,
+        payment: Box::new(swap_instruction),
+    }
+    .pack();
+    // `[sender, vault_pda_data, vault_pda, token accounts.., system_program, config,
+    // dex_fee_account]`
+    payment
+        .accounts
+        .push(AccountMeta::new(*dex_fee_account, false));
+    Some(payment)
+}
+
+/// Creates the config account with `admin` and `settings`, signed and paid for by the program's
+/// `upgrade_authority` and signed by `admin`.
+pub fn initialize_config(
+    program_id: &Pubkey,
+    upgrade_authority: &Pubkey,
+    admin: &Pubkey,
+    settings: ConfigSettings,
+) -> Instruction {
+    let (config, _) = find_config_address(program_id);
+    let (program_data, _) =
+        Pubkey::find_program_address(&[program_id.as_ref()], &bpf_loader_upgradeable::id());
+    Instruction {
+        program_id: *program_id,
+        accounts: vec![
+            AccountMeta::new(*upgrade_authority, true),
+            AccountMeta::new(config, false),
+            AccountMeta::new_readonly(program_data, false),
+            AccountMeta::new_readonly(*admin, true),
+            AccountMeta::new_readonly(system_program::id(), false),
+        ],
+        data: AtomicSwapInstruction::InitializeConfig {
+            admin: *admin,
+            settings,
+        }
+        .pack(),
+    }
+}
+
+/// Replaces the config settings, signed by the config `admin`.
+pub fn update_config(program_id: &Pubkey, admin: &Pubkey, settings: ConfigSettings) -> Instruction {
+    config_admin_instruction(
+        program_id,
+        admin,
+        AtomicSwapInstruction::UpdateConfig { settings },
+    )
+}
+
+/// Proposes `new_admin` as the config admin, signed by the current `admin`. It only becomes the
+/// admin once it signs [`accept_config_admin`].
+pub fn propose_config_admin(
+    program_id: &Pubkey,
+    admin: &Pubkey,
+    new_admin: &Pubkey,
+) -> Instruction {
+    config_admin_instruction(
+        program_id,
+        admin,
+        AtomicSwapInstruction::ProposeConfigAdmin { admin: *new_admin },
+    )
+}
+
+/// Makes the proposed `new_admin` the config admin, signed by it.
+pub fn accept_config_admin(program_id: &Pubkey, new_admin: &Pubkey) -> Instruction {
+    config_admin_instruction(
+        program_id,
+        new_admin,
+        AtomicSwapInstruction::AcceptConfigAdmin,
+    )
+}
+
+/// Pauses or resumes new payments, signed by the config `admin`.
+pub fn set_paused(program_id: &Pubkey, admin: &Pubkey, paused: bool) -> Instruction {
+    config_admin_instruction(
+        program_id,
+        admin,
+        AtomicSwapInstruction::SetPaused { paused },
+    )
+}
+
+fn config_admin_instruction(
+    program_id: &Pubkey,
+    admin: &Pubkey,
+    instruction: AtomicSwapInstruction,
+) -> Instruction {
+    let (config, _) = find_config_address(program_id);
+    Instruction {
+        program_id: *program_id,
+        accounts: vec![
+            AccountMeta::new_readonly(*admin, true),
+            AccountMeta::new(config, false),
+        ],
+        data: instruction.pack(),
+    }
+}
+
+/// Spends a payment on behalf of `receiver`, paying the relayer fee it was created with to
+/// `relayer`. `token_accounts` hold the receiver's token account and are followed by the relayer's
+/// token account receiving the fee of SPL token payments, which can be left out when the payment
+/// has no relayer fee. `None` when `secret` is invalid, see [`receiver_spend`].
+#[allow(clippy::too_many_arguments)]
+pub fn relayed_receiver_spend(
+    program_id: &Pubkey,
+    relayer: &Pubkey,
+    receiver: &Pubkey,
+    sender: &Pubkey,
+    secret: &[u8],
+    secret_hash_algorithm: SecretHashAlgorithm,
+    lock_time: u64,
+    amount: u64,
+    token_accounts: Option<(&SplTokenAccounts, Option<&Pubkey>)>,
+) -> Option<Instruction> {
+    if !is_valid_secret(secret) {
+        return None;
+    }
+    let secret_hash = secret_hash_algorithm.hash_secret(secret);
+    let mint = token_accounts.map(|(accounts, _)| &accounts.mint);
+    let payment_seeds = PaymentSeeds::new(lock_time, &secret_hash, sender, receiver, mint);
+    let mut instruction = swap_instruction(
+        program_id,
+        relayer,
+        &payment_seeds,
+        token_accounts.map(|(accounts, _)| accounts),
+        false,
+        |vault_bump_seed, vault_bump_seed_data| AtomicSwapInstruction::RelayedReceiverSpend {
+            secret: secret.to_vec(),
+            lock_time,
+            amount,
+            sender: *sender,
+            token_program: token_program(token_accounts.map(|(accounts, _)| accounts)),
+            vault_bump_seed,
+            vault_bump_seed_data,
+        },
+    );
+    // `[relayer, vault_pda_data, vault_pda, receiver, token accounts.., relayer_token_account?,
+    // system_program]`
+    instruction
+        .accounts
+        .insert(3, AccountMeta::new(*receiver, false));
+    if let Some((_, Some(relayer_token_account))) = token_accounts {
+        let system_program = instruction.accounts.len() - 1;
+        instruction.accounts.insert(
+            system_program,
+            AccountMeta::new(*relayer_token_account, false),
+        );
+    }
+    Some(instruction)
+}
+
+/// `amount` is the amount held by the vault, see [`receiver_spend`].
+pub fn sender_refund(
+    program_id: &Pubkey,
+    sender: &Pubkey,
+    receiver: &Pubkey,
+    secret_hash: [u8; 32],
+    lock_time: u64,
+    amount: u64,
+    token_accounts: Option<&SplTokenAccounts>,
+) -> Instruction {
+    let mint = token_accounts.map(|accounts| &accounts.mint);
+    let payment_seeds = PaymentSeeds::new(lock_time, &secret_hash, sender, receiver, mint);
+    swap_instruction(
+        program_id,
+        sender,
+        &payment_seeds,
+        token_accounts,
+        false,
+        |vault_bump_seed, vault_bump_seed_data| AtomicSwapInstruction::SenderRefund {
+            secret_hash,
+            lock_time,
+            amount,
+            receiver: *receiver,
+            token_program: token_program(token_accounts),
+            vault_bump_seed,
+            vault_bump_seed_data,
+        },
+    )
+}
+
+/// Refunds a payment to `sender` once its lock time has passed, signed and paid for by `relayer`.
+/// `token_accounts` hold the sender's token account, see [`sender_refund`].
+#[allow(clippy::too_many_arguments)]
+pub fn relayed_sender_refund(
+    program_id: &Pubkey,
+    relayer: &Pubkey,
+    sender: &Pubkey,
+    receiver: &Pubkey,
+    secret_hash: [u8; 32],
+    lock_time: u64,
+    amount: u64,
+    token_accounts: Option<&SplTokenAccounts>,
+) -> Instruction {
+    let mint = token_accounts.map(|accounts| &accounts.mint);
+    let payment_seeds = PaymentSeeds::new(lock_time, &secret_hash, sender, receiver, mint);
+    let mut instruction = swap_instruction(
+        program_id,
+        relayer,
+        &payment_seeds,
+        token_accounts,
+        false,
+        |vault_bump_seed, vault_bump_seed_data| AtomicSwapInstruction::RelayedSenderRefund {
+            secret_hash,
+            lock_time,
+            amount,
+            receiver: *receiver,
+            token_program: token_program(token_accounts),
+            vault_bump_seed,
+            vault_bump_seed_data,
+        },
+    );
+    // `[relayer, vault_pda_data, vault_pda, sender, token accounts.., system_program]`
+    instruction
+        .accounts
+        .insert(3, AccountMeta::new(*sender, false));
+    instruction
+}
+
+/// Closes the accounts of a spent or refunded payment, returning their rent to `sender`.
+pub fn close_payment(
+    program_id: &Pubkey,
+    sender: &Pubkey,
+    receiver: &Pubkey,
+    secret_hash: [u8; 32],
+    lock_time: u64,
+    amount: u64,
+    token_accounts: Option<&SplTokenAccounts>,
+) -> Instruction {
+    let mint = token_accounts.map(|accounts| &accounts.mint);
+    let payment_seeds = PaymentSeeds::new(lock_time, &secret_hash, sender, receiver, mint);
+    swap_instruction(
+        program_id,
+        sender,
+        &payment_seeds,
+        token_accounts,
+        true,
+        |vault_bump_seed, vault_bump_seed_data| AtomicSwapInstruction::ClosePayment {
+            secret_hash,
+            lock_time,
+            amount,
+            receiver: *receiver,
+            token_program: token_program(token_accounts),
+            vault_bump_seed,
+            vault_bump_seed_data,
+        },
+    )
+}
+
+/// Spends a payment from its account alone, `None` when the account predates the stored terms or
+/// `secret` is invalid, see [`receiver_spend`]. `token_accounts` must hold the stored mint and
+/// token program.
+pub fn compact_receiver_spend(
+    program_id: &Pubkey,
+    payment: &Payment,
+    secret: &[u8],
+    token_accounts: Option<&SplTokenAccounts>,
+) -> Option<Instruction> {
+    let terms = payment.terms.as_ref()?;
+    if !is_valid_secret(secret) {
+        return None;
+    }
+    let mint = (terms.token_program != Pubkey::default()).then_some(&terms.mint);
+    let payment_seeds = PaymentSeeds::new(
+        payment.lock_time,
+        &terms.secret_hash,
+        &terms.sender,
+        &terms.receiver,
+        mint,
+    );
+    Some(swap_instruction(
+        program_id,
+        &terms.receiver,
+        &payment_seeds,
+        token_accounts,
+        false,
+        |_, _| AtomicSwapInstruction::CompactReceiverSpend {
+            secret: secret.to_vec(),
+        },
+    ))
+}
+
+/// Refunds a payment from its account alone, see [`compact_receiver_spend`].
+pub fn compact_sender_refund(
+    program_id: &Pubkey,
+    payment: &Payment,
+    token_accounts: Option<&SplTokenAccounts>,
+) -> Option<Instruction> {
+    let terms = payment.terms.as_ref()?;
+    let mint = (terms.token_program != Pubkey::default()).then_some(&terms.mint);
+    let payment_seeds = PaymentSeeds::new(
+        payment.lock_time,
+        &terms.secret_hash,
+        &terms.sender,
+        &terms.receiver,
+        mint,
+    );
+    Some(swap_instruction(
+        program_id,
+        &terms.sender,
+        &payment_seeds,
+        token_accounts,
+        false,
+        |_, _| AtomicSwapInstruction::CompactSenderRefund,
+    ))
+}
+
+/// Queries the status of a payment, to be simulated or invoked through CPI and read back with
+/// `PaymentStatus::unpack`. `amount` is the amount held by the vault, see [`receiver_spend`], and
+/// `token` the mint and token program of SPL token payments.
+pub fn query_payment(
+    program_id: &Pubkey,
+    sender: &Pubkey,
+    receiver: &Pubkey,
+    secret_hash: [u8; 32],
+    lock_time: u64,
+    amount: u64,
+    token: Option<(&Pubkey, &Pubkey)>,
+) -> Instruction {
+    let mint = token.map(|(mint, _)| mint);
+    let payment_seeds = PaymentSeeds::new(lock_time, &secret_hash, sender, receiver, mint);
+    let (_, (vault_pda_data, _)) = payment_seeds.find_vault_addresses(program_id);
+    let (mint, token_program) =
+        token.map_or((Pubkey::default(), Pubkey::default()), |(m, t)| (*m, *t));
+    Instruction {
+        program_id: *program_id,
+        accounts: vec![AccountMeta::new_readonly(vault_pda_data, false)],
+        data: AtomicSwapInstruction::QueryPayment {
+            secret_hash,
+            lock_time,
+            amount,
+            sender: *sender,
+            receiver: *receiver,
+            mint,
+            token_program,
+        }
+        .pack(),
+    }
+}
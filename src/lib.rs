@@ -1,7 +1,11 @@
-mod error_code;
-mod instruction;
-mod payment;
-mod satomic_swap;
+pub mod config;
+pub mod error;
+pub mod event;
+pub mod instruction;
+pub mod lock_time;
+pub mod payment;
+pub mod pda;
+pub mod satomic_swap;
 mod swap_functions;
 #[cfg(test)]
 mod tests;
@@ -0,0 +1,64 @@
+use solana_program::clock::{Clock, Epoch, Slot, DEFAULT_MS_PER_SLOT};
+use solana_program::epoch_schedule::EpochSchedule;
+use std::time::Duration;
+
+/// Clock a payment's `lock_time` is measured against.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum LockTimeKind {
+    #[default]
+    UnixTimestamp,
+    Slot,
+    Epoch,
+}
+
+impl LockTimeKind {
+    pub fn from_u8(byte: u8) -> Option<Self> {
+        match byte {
+            0 => Some(LockTimeKind::UnixTimestamp),
+            1 => Some(LockTimeKind::Slot),
+            2 => Some(LockTimeKind::Epoch),
+            _ => None,
+        }
+    }
+
+    pub fn to_u8(self) -> u8 {
+        match self {
+            LockTimeKind::UnixTimestamp => 0,
+            LockTimeKind::Slot => 1,
+            LockTimeKind::Epoch => 2,
+        }
+    }
+
+    /// Whether `lock_time` has passed, after which the sender can refund. A spend deadline passes
+    /// the same way, so the receiver can still spend when the clock reads exactly the deadline.
+    pub fn has_passed(self, lock_time: u64, clock: &Clock) -> bool {
+        let now = match self {
+            LockTimeKind::UnixTimestamp => clock.unix_timestamp as u64,
+            LockTimeKind::Slot => clock.slot,
+            LockTimeKind::Epoch => clock.epoch,
+        };
+        now > lock_time
+    }
+}
+
+/// Slot time lock times are computed with, half the `DEFAULT_MS_PER_SLOT` target. Slots are only
+/// produced at that target on average and can be shorter, which would let the refund open early.
+pub const ASSUMED_MIN_MS_PER_SLOT: u64 = DEFAULT_MS_PER_SLOT / 2;
+
+/// Slot lock time that passes `duration` after `current_slot` if slots are no shorter than
+/// [`ASSUMED_MIN_MS_PER_SLOT`]. That is not guaranteed by the cluster, so `duration` should keep a
+/// margin of its own; slots at the target delay the refund to about twice `duration`.
+pub fn slot_lock_time(current_slot: Slot, duration: Duration) -> Slot {
+    let slots = (duration.as_millis()).div_ceil(ASSUMED_MIN_MS_PER_SLOT as u128);
+    current_slot.saturating_add(slots.try_into().unwrap_or(u64::MAX))
+}
+
+/// Epoch lock time that passes `duration` after `current_slot` under the same assumption as
+/// [`slot_lock_time`].
+pub fn epoch_lock_time(
+    epoch_schedule: &EpochSchedule,
+    current_slot: Slot,
+    duration: Duration,
+) -> Epoch {
+    epoch_schedule.get_epoch(slot_lock_time(current_slot, duration))
+}
@@ -1,13 +1,97 @@
+use crate::instruction::MAX_SECRET_LEN;
+use crate::lock_time::LockTimeKind;
+use ripemd::{Digest, Ripemd160};
+use solana_program::clock::Clock;
+use solana_program::entrypoint::ProgramResult;
+use solana_program::hash::hash;
+use solana_program::keccak;
 use solana_program::program_error::ProgramError;
+use solana_program::pubkey::Pubkey;
 
-#[derive(Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct Payment {
     pub payment_hash: [u8; 32],
     pub lock_time: u64,
     pub state: PaymentState,
+    pub secret_hash_algorithm: SecretHashAlgorithm,
+    pub lock_time_kind: LockTimeKind,
+    /// Last moment the receiver can spend, measured like `lock_time` and never after it.
+    pub spend_deadline: u64,
+    /// Swap terms, stored by payments created since the v2 layout and `None` for v1 accounts.
+    pub terms: Option<PaymentTerms>,
+    /// Secret revealed by the receiver spend, only stored by v2 accounts.
+    pub secret: Option<Vec<u8>>,
+    /// Part of the amount paid to whoever submits a relayed spend, committed to by the payment
+    /// hash. Only stored by v2 accounts, v1 payments have none.
+    pub relayer_fee: u64,
+    /// Lamports locked on top of the amount and paid to whoever submits a relayed spend or a
+    /// refund, committed to by the payment hash. Only stored by v2 accounts, v1 payments have none.
+    pub watcher_reward: u64,
+    /// Dex fee paid by `PaymentWithDexFee` alongside the payment, zero for payments made without
+    /// one. Only stored by v2 accounts.
+    pub dex_fee: u64,
 }
 
-#[derive(Debug, PartialEq)]
+/// Everything needed to spend or refund a payment without resending its parameters.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct PaymentTerms {
+    pub sender: Pubkey,
+    pub receiver: Pubkey,
+    /// The default pubkey for lamports payments, like `token_program`.
+    pub mint: Pubkey,
+    pub token_program: Pubkey,
+    pub secret_hash: [u8; 32],
+    /// Amount held by the vault and committed to by the payment hash.
+    pub amount: u64,
+    pub vault_bump_seed: u8,
+    pub vault_bump_seed_data: u8,
+    /// Unix timestamp of the block the payment was created in.
+    pub created_at: i64,
+}
+
+impl PaymentTerms {
+    pub const LEN: usize = 178;
+
+    fn unpack(input: &[u8]) -> Result<Self, ProgramError> {
+        if input.len() != Self::LEN {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        Ok(Self {
+            sender: Pubkey::new_from_array(read_array(input, 0)?),
+            receiver: Pubkey::new_from_array(read_array(input, 32)?),
+            mint: Pubkey::new_from_array(read_array(input, 64)?),
+            token_program: Pubkey::new_from_array(read_array(input, 96)?),
+            secret_hash: read_array(input, 128)?,
+            amount: u64::from_le_bytes(read_array(input, 160)?),
+            vault_bump_seed: input[168],
+            vault_bump_seed_data: input[169],
+            created_at: i64::from_le_bytes(read_array(input, 170)?),
+        })
+    }
+
+    fn pack(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(Self::LEN);
+        bytes.extend_from_slice(self.sender.as_ref());
+        bytes.extend_from_slice(self.receiver.as_ref());
+        bytes.extend_from_slice(self.mint.as_ref());
+        bytes.extend_from_slice(self.token_program.as_ref());
+        bytes.extend_from_slice(&self.secret_hash);
+        bytes.extend_from_slice(&self.amount.to_le_bytes());
+        bytes.push(self.vault_bump_seed);
+        bytes.push(self.vault_bump_seed_data);
+        bytes.extend_from_slice(&self.created_at.to_le_bytes());
+        bytes
+    }
+}
+
+fn read_array<const N: usize>(input: &[u8], offset: usize) -> Result<[u8; N], ProgramError> {
+    input
+        .get(offset..offset + N)
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or(ProgramError::InvalidAccountData)
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
 pub enum PaymentState {
     Uninitialized,
     PaymentSent,
@@ -15,11 +99,151 @@ pub enum PaymentState {
     SenderRefunded,
 }
 
-impl Payment {
+impl PaymentState {
+    pub fn from_u8(byte: u8) -> Option<Self> {
+        match byte {
+            0 => Some(PaymentState::Uninitialized),
+            1 => Some(PaymentState::PaymentSent),
+            2 => Some(PaymentState::ReceiverSpent),
+            3 => Some(PaymentState::SenderRefunded),
+            _ => None,
+        }
+    }
+
+    pub fn to_u8(self) -> u8 {
+        match self {
+            PaymentState::Uninitialized => 0,
+            PaymentState::PaymentSent => 1,
+            PaymentState::ReceiverSpent => 2,
+            PaymentState::SenderRefunded => 3,
+        }
+    }
+}
+
+/// Return data of `QueryPayment`, evaluated against the clock of the querying transaction.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct PaymentStatus {
+    pub state: PaymentState,
+    pub lock_time: u64,
+    pub lock_time_kind: LockTimeKind,
+    pub spend_deadline: u64,
+    /// The receiver can spend the payment now.
+    pub spendable: bool,
+    /// The sender can refund the payment now.
+    pub refundable: bool,
+}
+
+impl PaymentStatus {
+    pub const LEN: usize = 20;
+
+    pub fn new(payment: &Payment, clock: &Clock) -> Self {
+        let sent = payment.state == PaymentState::PaymentSent;
+        let lock_time_kind = payment.lock_time_kind;
+        Self {
+            state: payment.state,
+            lock_time: payment.lock_time,
+            lock_time_kind,
+            spend_deadline: payment.spend_deadline,
+            spendable: sent && !lock_time_kind.has_passed(payment.spend_deadline, clock),
+            refundable: sent && lock_time_kind.has_passed(payment.lock_time, clock),
+        }
+    }
+
     pub fn unpack(input: &[u8]) -> Result<Self, ProgramError> {
-        if input.len() != 41 {
+        if input.len() != Self::LEN {
             return Err(ProgramError::InvalidAccountData);
         }
+        let read_bool = |byte: u8| match byte {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(ProgramError::InvalidAccountData),
+        };
+        Ok(Self {
+            state: PaymentState::from_u8(input[0]).ok_or(ProgramError::InvalidAccountData)?,
+            lock_time: u64::from_le_bytes(read_array(input, 1)?),
+            lock_time_kind: LockTimeKind::from_u8(input[9])
+                .ok_or(ProgramError::InvalidAccountData)?,
+            spend_deadline: u64::from_le_bytes(read_array(input, 10)?),
+            spendable: read_bool(input[18])?,
+            refundable: read_bool(input[19])?,
+        })
+    }
+
+    pub fn pack(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(Self::LEN);
+        bytes.push(self.state.to_u8());
+        bytes.extend_from_slice(&self.lock_time.to_le_bytes());
+        bytes.push(self.lock_time_kind.to_u8());
+        bytes.extend_from_slice(&self.spend_deadline.to_le_bytes());
+        bytes.push(self.spendable as u8);
+        bytes.push(self.refundable as u8);
+        bytes
+    }
+}
+
+/// Hash committing to the swap secret. Hashes shorter than 32 bytes are stored zero-padded.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum SecretHashAlgorithm {
+    #[default]
+    Sha256,
+    /// `RIPEMD160(SHA256(secret))`, the `OP_HASH160` used by UTXO coin HTLCs.
+    Hash160,
+    /// Keccak-256, as committed to by EVM swap contracts.
+    Keccak256,
+}
+
+impl SecretHashAlgorithm {
+    pub fn from_u8(byte: u8) -> Option<Self> {
+        match byte {
+            0 => Some(SecretHashAlgorithm::Sha256),
+            1 => Some(SecretHashAlgorithm::Hash160),
+            2 => Some(SecretHashAlgorithm::Keccak256),
+            _ => None,
+        }
+    }
+
+    pub fn to_u8(self) -> u8 {
+        match self {
+            SecretHashAlgorithm::Sha256 => 0,
+            SecretHashAlgorithm::Hash160 => 1,
+            SecretHashAlgorithm::Keccak256 => 2,
+        }
+    }
+
+    pub fn hash_secret(self, secret: &[u8]) -> [u8; 32] {
+        match self {
+            SecretHashAlgorithm::Sha256 => hash(secret).to_bytes(),
+            SecretHashAlgorithm::Hash160 => {
+                let mut secret_hash = [0; 32];
+                secret_hash[..20].copy_from_slice(&Ripemd160::digest(hash(secret).to_bytes()));
+                secret_hash
+            }
+            SecretHashAlgorithm::Keccak256 => keccak::hash(secret).to_bytes(),
+        }
+    }
+}
+
+impl Payment {
+    /// v2 layout: the v1 fields, the secret hash algorithm, the lock time kind, the spend
+    /// deadline, the `V2` version byte, the `PaymentTerms`, the secret, stored zero-padded after
+    /// its length, the relayer fee, the watcher reward and the dex fee.
+    pub const LEN: usize = Self::SECRET_OFFSET + Self::SECRET_LEN + 24;
+    /// Offset of the secret length in v2 accounts.
+    pub const SECRET_OFFSET: usize = Self::VERSION_OFFSET + 1 + PaymentTerms::LEN;
+    const SECRET_LEN: usize = 1 + MAX_SECRET_LEN;
+    /// Offset of the version byte in v2 accounts.
+    pub const VERSION_OFFSET: usize = 51;
+    /// v1 layout: the payment hash, the lock time and the state. Fields v1 accounts predate take
+    /// their default value.
+    pub const LEGACY_LEN: usize = 41;
+    pub const V2: u8 = 2;
+
+    pub fn unpack(input: &[u8]) -> Result<Self, ProgramError> {
+        let v2 = match input.len() {
+            Self::LEGACY_LEN => None,
+            Self::LEN if input[Self::VERSION_OFFSET] == Self::V2 => Some(input),
+            _ => return Err(ProgramError::InvalidAccountData),
+        };
 
         let payment_hash = input[0..32]
             .try_into()
@@ -31,21 +255,73 @@ impl Payment {
                 .map_err(|_| ProgramError::InvalidAccountData)?,
         );
 
-        let state = match input[40] {
-            0 => PaymentState::Uninitialized,
-            1 => PaymentState::PaymentSent,
-            2 => PaymentState::ReceiverSpent,
-            3 => PaymentState::SenderRefunded,
-            _ => return Err(ProgramError::InvalidAccountData),
+        let state = PaymentState::from_u8(input[40]).ok_or(ProgramError::InvalidAccountData)?;
+
+        let Some(input) = v2 else {
+            return Ok(Self {
+                payment_hash,
+                lock_time,
+                state,
+                secret_hash_algorithm: SecretHashAlgorithm::Sha256,
+                lock_time_kind: LockTimeKind::UnixTimestamp,
+                spend_deadline: lock_time,
+                terms: None,
+                secret: None,
+                relayer_fee: 0,
+                watcher_reward: 0,
+                dex_fee: 0,
+            });
         };
 
+        let secret_hash_algorithm =
+            SecretHashAlgorithm::from_u8(input[41]).ok_or(ProgramError::InvalidAccountData)?;
+
+        let lock_time_kind =
+            LockTimeKind::from_u8(input[42]).ok_or(ProgramError::InvalidAccountData)?;
+
+        let spend_deadline = u64::from_le_bytes(read_array(input, 43)?);
+
+        let (terms, rest) = input[Self::VERSION_OFFSET + 1..].split_at(PaymentTerms::LEN);
+        let terms = Some(PaymentTerms::unpack(terms)?);
+        let secret = Self::unpack_secret(&rest[..Self::SECRET_LEN])?;
+        let relayer_fee = u64::from_le_bytes(read_array(rest, Self::SECRET_LEN)?);
+        let watcher_reward = u64::from_le_bytes(read_array(rest, Self::SECRET_LEN + 8)?);
+        let dex_fee = u64::from_le_bytes(read_array(rest, Self::SECRET_LEN + 16)?);
+
         Ok(Self {
             payment_hash,
             lock_time,
             state,
+            secret_hash_algorithm,
+            lock_time_kind,
+            spend_deadline,
+            terms,
+            secret,
+            relayer_fee,
+            watcher_reward,
+            dex_fee,
         })
     }
 
+    fn unpack_secret(input: &[u8]) -> Result<Option<Vec<u8>>, ProgramError> {
+        match input.split_first() {
+            None | Some((0, _)) => Ok(None),
+            Some((&len, secret)) => secret
+                .get(..len as usize)
+                .map(|secret| Some(secret.to_vec()))
+                .ok_or(ProgramError::InvalidAccountData),
+        }
+    }
+
+    /// Secret the receiver spent the payment with, from which the other leg of the swap can be
+    /// completed. `None` until spent and for v1 accounts.
+    pub fn revealed_secret(&self) -> Option<&[u8]> {
+        match self.state {
+            PaymentState::ReceiverSpent => self.secret.as_deref(),
+            _ => None,
+        }
+    }
+
     pub fn pack(&self) -> Vec<u8> {
         let mut bytes = Vec::new();
 
@@ -53,14 +329,37 @@ impl Payment {
 
         bytes.extend_from_slice(&self.lock_time.to_le_bytes());
 
-        let state_byte = match self.state {
-            PaymentState::Uninitialized => 0,
-            PaymentState::PaymentSent => 1,
-            PaymentState::ReceiverSpent => 2,
-            PaymentState::SenderRefunded => 3,
-        };
-        bytes.push(state_byte);
+        bytes.push(self.state.to_u8());
+
+        if let Some(terms) = &self.terms {
+            bytes.push(self.secret_hash_algorithm.to_u8());
+            bytes.push(self.lock_time_kind.to_u8());
+            bytes.extend_from_slice(&self.spend_deadline.to_le_bytes());
+            bytes.push(Self::V2);
+            bytes.extend_from_slice(&terms.pack());
+            let secret = self.secret.as_deref().unwrap_or_default();
+            bytes.push(secret.len() as u8);
+            bytes.extend_from_slice(secret);
+            bytes.resize(Self::SECRET_OFFSET + Self::SECRET_LEN, 0);
+            bytes.extend_from_slice(&self.relayer_fee.to_le_bytes());
+            bytes.extend_from_slice(&self.watcher_reward.to_le_bytes());
+            bytes.extend_from_slice(&self.dex_fee.to_le_bytes());
+        }
 
         bytes
     }
+
+    /// Writes the payment into account data, keeping the layout the account was created with.
+    pub fn pack_into(&self, data: &mut [u8]) -> ProgramResult {
+        let bytes = self.pack();
+        if data.len() != bytes.len() {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        // v1 accounts only hold the fields they predate at their default value.
+        if Self::unpack(&bytes)? != *self {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        data.copy_from_slice(&bytes);
+        Ok(())
+    }
 }
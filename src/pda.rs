@@ -0,0 +1,146 @@
+use crate::error::SwapError;
+use solana_program::entrypoint::ProgramResult;
+use solana_program::pubkey::Pubkey;
+
+pub const VAULT_SEED: &[u8] = b"swap";
+pub const VAULT_DATA_SEED: &[u8] = b"swap_data";
+pub const CONFIG_SEED: &[u8] = b"config";
+
+/// `(address, bump)` of the program's config account.
+pub fn find_config_address(program_id: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[CONFIG_SEED], program_id)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SeedScheme {
+    /// `[prefix, lock_time, secret_hash]`, used by payments created before the vaults were bound
+    /// to the swap parties. Only read by spend, refund and close.
+    Legacy,
+    /// `[prefix, lock_time, secret_hash, sender, receiver]`, followed by the mint for SPL payments.
+    Bound,
+}
+
+pub struct PaymentSeeds<'a> {
+    lock_time: [u8; 8],
+    secret_hash: &'a [u8; 32],
+    sender: &'a Pubkey,
+    receiver: &'a Pubkey,
+    mint: Option<&'a Pubkey>,
+}
+
+impl<'a> PaymentSeeds<'a> {
+    pub fn new(
+        lock_time: u64,
+        secret_hash: &'a [u8; 32],
+        sender: &'a Pubkey,
+        receiver: &'a Pubkey,
+        mint: Option<&'a Pubkey>,
+    ) -> Self {
+        Self {
+            lock_time: lock_time.to_le_bytes(),
+            secret_hash,
+            sender,
+            receiver,
+            mint,
+        }
+    }
+
+    /// Seeds of the PDA identified by `prefix`, followed by `bump` when it is not empty.
+    pub fn seeds<'b>(
+        &'b self,
+        prefix: &'b [u8],
+        scheme: SeedScheme,
+        bump: &'b [u8],
+    ) -> Vec<&'b [u8]> {
+        let mut seeds: Vec<&[u8]> = vec![prefix, &self.lock_time, self.secret_hash];
+        if scheme == SeedScheme::Bound {
+            seeds.push(self.sender.as_ref());
+            seeds.push(self.receiver.as_ref());
+            if let Some(mint) = self.mint {
+                seeds.push(mint.as_ref());
+            }
+        }
+        if !bump.is_empty() {
+            seeds.push(bump);
+        }
+        seeds
+    }
+
+    /// Canonical `(address, bump)` of the vault and of the vault data account for new payments.
+    pub fn find_vault_addresses(&self, program_id: &Pubkey) -> ((Pubkey, u8), (Pubkey, u8)) {
+        (
+            Pubkey::find_program_address(
+                &self.seeds(VAULT_SEED, SeedScheme::Bound, &[]),
+                program_id,
+            ),
+            Pubkey::find_program_address(
+                &self.seeds(VAULT_DATA_SEED, SeedScheme::Bound, &[]),
+                program_id,
+            ),
+        )
+    }
+
+    /// Detects which scheme `vault_pda_data` was derived with, falling back to `Legacy`.
+    pub fn scheme(&self, program_id: &Pubkey, vault_pda_data: &Pubkey, bump: u8) -> SeedScheme {
+        let bump = [bump];
+        let seeds = self.seeds(VAULT_DATA_SEED, SeedScheme::Bound, &bump);
+        match Pubkey::create_program_address(&seeds, program_id) {
+            Ok(address) if address == *vault_pda_data => SeedScheme::Bound,
+            _ => SeedScheme::Legacy,
+        }
+    }
+
+    /// Re-derives the PDA identified by `prefix` and checks that it is `address` and that `bump`
+    /// is the canonical one returned by `find_program_address`.
+    fn verify_address(
+        &self,
+        program_id: &Pubkey,
+        prefix: &[u8],
+        scheme: SeedScheme,
+        bump: u8,
+        address: &Pubkey,
+        mismatch_error: SwapError,
+    ) -> ProgramResult {
+        let bump_seed = [bump];
+        let seeds = self.seeds(prefix, scheme, &bump_seed);
+        match Pubkey::create_program_address(&seeds, program_id) {
+            Ok(derived) if derived == *address => {}
+            _ => return Err(mismatch_error.into()),
+        }
+        for higher_bump in (bump..=u8::MAX).skip(1) {
+            let higher_bump_seed = [higher_bump];
+            let seeds = self.seeds(prefix, scheme, &higher_bump_seed);
+            if Pubkey::create_program_address(&seeds, program_id).is_ok() {
+                return Err(SwapError::NonCanonicalBump.into());
+            }
+        }
+        Ok(())
+    }
+
+    pub fn verify_vault_addresses(
+        &self,
+        program_id: &Pubkey,
+        scheme: SeedScheme,
+        vault_pda: &Pubkey,
+        vault_bump_seed: u8,
+        vault_pda_data: &Pubkey,
+        vault_bump_seed_data: u8,
+    ) -> ProgramResult {
+        self.verify_address(
+            program_id,
+            VAULT_SEED,
+            scheme,
+            vault_bump_seed,
+            vault_pda,
+            SwapError::VaultAddressMismatch,
+        )?;
+        self.verify_address(
+            program_id,
+            VAULT_DATA_SEED,
+            scheme,
+            vault_bump_seed_data,
+            vault_pda_data,
+            SwapError::VaultDataAddressMismatch,
+        )
+    }
+}
@@ -1,107 +1,275 @@
+use crate::error::SwapError;
 use crate::instruction::{
-    AtomicSwapInstruction, LamportsPaymentParams, ReceiverSpendParams, SPLTokenPaymentParams,
-    SenderRefundParams,
+    AtomicSwapInstruction, ClosePaymentParams, LamportsPaymentParams, QueryPaymentParams,
+    ReceiverSpendParams, SPLTokenPaymentParams, SenderRefundParams,
 };
 use crate::swap_functions::SwapFunctions;
 use solana_program::{
-    account_info::AccountInfo, entrypoint, entrypoint::ProgramResult, pubkey::Pubkey,
+    account_info::AccountInfo, entrypoint::ProgramResult, program_error::PrintProgramError,
+    pubkey::Pubkey,
 };
 
-entrypoint!(process_instruction);
+#[cfg(not(feature = "no-entrypoint"))]
+solana_program::entrypoint!(process_instruction);
 
 pub fn process_instruction(
     program_id: &Pubkey,
     accounts: &[AccountInfo],
     instruction_data: &[u8],
 ) -> ProgramResult {
-    let instruction = AtomicSwapInstruction::unpack(instruction_data)?;
+    let result = AtomicSwapInstruction::unpack(instruction_data)
+        .and_then(|instruction| dispatch(program_id, accounts, instruction));
+    if let Err(error) = &result {
+        error.print::<SwapError>();
+    }
+    result
+}
 
+fn dispatch(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    instruction: AtomicSwapInstruction,
+) -> ProgramResult {
     match instruction {
-        AtomicSwapInstruction::LamportsPayment {
+        payment @ (AtomicSwapInstruction::LamportsPayment { .. }
+        | AtomicSwapInstruction::SPLTokenPayment { .. }) => {
+            dispatch_payment(program_id, accounts, payment, 0)
+        }
+        AtomicSwapInstruction::ReceiverSpend {
+            secret,
+            lock_time,
+            amount,
+            sender,
+            token_program,
+            vault_bump_seed,
+            vault_bump_seed_data,
+        } => SwapFunctions::receiver_spend(
+            program_id,
+            accounts,
+            ReceiverSpendParams {
+                secret,
+                lock_time,
+                amount,
+                sender,
+                token_program,
+                vault_bump_seed,
+                vault_bump_seed_data,
+            },
+        ),
+        AtomicSwapInstruction::RelayedReceiverSpend {
+            secret,
+            lock_time,
+            amount,
+            sender,
+            token_program,
+            vault_bump_seed,
+            vault_bump_seed_data,
+        } => SwapFunctions::relayed_receiver_spend(
+            program_id,
+            accounts,
+            ReceiverSpendParams {
+                secret,
+                lock_time,
+                amount,
+                sender,
+                token_program,
+                vault_bump_seed,
+                vault_bump_seed_data,
+            },
+        ),
+        AtomicSwapInstruction::SenderRefund {
             secret_hash,
             lock_time,
             amount,
             receiver,
-            rent_exemption_lamports,
+            token_program,
             vault_bump_seed,
             vault_bump_seed_data,
-        } => SwapFunctions::lamports_payment(
+        } => SwapFunctions::sender_refund(
             program_id,
             accounts,
-            LamportsPaymentParams {
+            SenderRefundParams {
                 secret_hash,
                 lock_time,
                 amount,
                 receiver,
-                rent_exemption_lamports,
+                token_program,
                 vault_bump_seed,
                 vault_bump_seed_data,
             },
         ),
-        AtomicSwapInstruction::SPLTokenPayment {
+        AtomicSwapInstruction::RelayedSenderRefund {
             secret_hash,
             lock_time,
             amount,
             receiver,
             token_program,
-            rent_exemption_lamports,
             vault_bump_seed,
             vault_bump_seed_data,
-        } => SwapFunctions::spl_token_payment(
+        } => SwapFunctions::relayed_sender_refund(
             program_id,
             accounts,
-            SPLTokenPaymentParams {
+            SenderRefundParams {
                 secret_hash,
                 lock_time,
                 amount,
                 receiver,
                 token_program,
-                rent_exemption_lamports,
                 vault_bump_seed,
                 vault_bump_seed_data,
             },
         ),
-        AtomicSwapInstruction::ReceiverSpend {
-            secret,
+        AtomicSwapInstruction::PaymentWithDexFee { dex_fee, payment } => {
+            let (amount, token_program) = match *payment {
+                AtomicSwapInstruction::LamportsPayment { amount, .. } => {
+                    (amount, Pubkey::default())
+                }
+                AtomicSwapInstruction::SPLTokenPayment {
+                    amount,
+                    token_program,
+                    ..
+                } => (amount, token_program),
+                _ => return Err(SwapError::InvalidAtomicSwapInstruction.into()),
+            };
+            SwapFunctions::pay_dex_fee(program_id, accounts, dex_fee, amount, &token_program)?;
+            dispatch_payment(program_id, accounts, *payment, dex_fee)
+        }
+        AtomicSwapInstruction::InitializeConfig { admin, settings } => {
+            SwapFunctions::initialize_config(program_id, accounts, admin, settings)
+        }
+        AtomicSwapInstruction::UpdateConfig { settings } => {
+            SwapFunctions::update_config(program_id, accounts, settings)
+        }
+        AtomicSwapInstruction::ProposeConfigAdmin { admin } => {
+            SwapFunctions::propose_config_admin(program_id, accounts, admin)
+        }
+        AtomicSwapInstruction::AcceptConfigAdmin => {
+            SwapFunctions::accept_config_admin(program_id, accounts)
+        }
+        AtomicSwapInstruction::SetPaused { paused } => {
+            SwapFunctions::set_paused(program_id, accounts, paused)
+        }
+        AtomicSwapInstruction::CompactReceiverSpend { secret } => {
+            SwapFunctions::compact_receiver_spend(program_id, accounts, secret)
+        }
+        AtomicSwapInstruction::CompactSenderRefund => {
+            SwapFunctions::compact_sender_refund(program_id, accounts)
+        }
+        AtomicSwapInstruction::QueryPayment {
+            secret_hash,
             lock_time,
             amount,
             sender,
+            receiver,
+            mint,
+            token_program,
+        } => SwapFunctions::query_payment(
+            program_id,
+            accounts,
+            QueryPaymentParams {
+                secret_hash,
+                lock_time,
+                amount,
+                sender,
+                receiver,
+                mint,
+                token_program,
+            },
+        ),
+        AtomicSwapInstruction::ClosePayment {
+            secret_hash,
+            lock_time,
+            amount,
+            receiver,
             token_program,
             vault_bump_seed,
             vault_bump_seed_data,
-        } => SwapFunctions::receiver_spend(
+        } => SwapFunctions::close_payment(
             program_id,
             accounts,
-            ReceiverSpendParams {
-                secret,
+            ClosePaymentParams {
+                secret_hash,
                 lock_time,
                 amount,
-                sender,
+                receiver,
                 token_program,
                 vault_bump_seed,
                 vault_bump_seed_data,
             },
         ),
-        AtomicSwapInstruction::SenderRefund {
+    }
+}
+
+/// Dispatches a payment instruction, `dex_fee` being the fee `PaymentWithDexFee` paid ahead of it.
+fn dispatch_payment(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    payment: AtomicSwapInstruction,
+    dex_fee: u64,
+) -> ProgramResult {
+    match payment {
+        AtomicSwapInstruction::LamportsPayment {
+            secret_hash,
+            secret_hash_algorithm,
+            lock_time,
+            lock_time_kind,
+            spend_deadline,
+            amount,
+            relayer_fee,
+            watcher_reward,
+            receiver,
+            vault_bump_seed,
+            vault_bump_seed_data,
+        } => SwapFunctions::lamports_payment(
+            program_id,
+            accounts,
+            LamportsPaymentParams {
+                secret_hash,
+                secret_hash_algorithm,
+                lock_time,
+                lock_time_kind,
+                spend_deadline,
+                amount,
+                relayer_fee,
+                watcher_reward,
+                receiver,
+                vault_bump_seed,
+                vault_bump_seed_data,
+                dex_fee,
+            },
+        ),
+        AtomicSwapInstruction::SPLTokenPayment {
             secret_hash,
+            secret_hash_algorithm,
             lock_time,
+            lock_time_kind,
+            spend_deadline,
             amount,
+            relayer_fee,
+            watcher_reward,
             receiver,
             token_program,
             vault_bump_seed,
             vault_bump_seed_data,
-        } => SwapFunctions::sender_refund(
+        } => SwapFunctions::spl_token_payment(
             program_id,
             accounts,
-            SenderRefundParams {
+            SPLTokenPaymentParams {
                 secret_hash,
+                secret_hash_algorithm,
                 lock_time,
+                lock_time_kind,
+                spend_deadline,
                 amount,
+                relayer_fee,
+                watcher_reward,
                 receiver,
                 token_program,
                 vault_bump_seed,
                 vault_bump_seed_data,
+                dex_fee,
             },
         ),
+        _ => Err(SwapError::InvalidAtomicSwapInstruction.into()),
     }
 }
@@ -1,32 +1,148 @@
-use crate::error_code::{
-    AMOUNT_ZERO, INVALID_OWNER, INVALID_PAYMENT_HASH, INVALID_PAYMENT_STATE, NOT_SUPPORTED,
-    RECEIVER_SET_TO_DEFAULT, SENDER_ACCOUNT_NOT_SIGNER, SENDER_ACCOUNT_NOT_WRITABLE,
-    SWAP_ACCOUNT_NOT_FOUND, VAULT_PDA_DATA_NOT_WRITABLE, VAULT_PDA_NOT_WRITABLE,
-    VAULT_PDA_PROGRAM_NOT_OWNER, WAIT_FOR_LOCK_TIME,
-};
+use crate::config::{Config, ConfigSettings};
+use crate::error::SwapError;
+use crate::event::{SwapEvent, SwapEventKind};
 use crate::instruction::{
-    LamportsPaymentParams, ReceiverSpendParams, SPLTokenPaymentParams, SenderRefundParams,
+    ClosePaymentParams, LamportsPaymentParams, QueryPaymentParams, ReceiverSpendParams,
+    SPLTokenPaymentParams, SenderRefundParams,
+};
+use crate::payment::{Payment, PaymentState, PaymentStatus, PaymentTerms, SecretHashAlgorithm};
+use crate::pda::{
+    find_config_address, PaymentSeeds, SeedScheme, CONFIG_SEED, VAULT_DATA_SEED, VAULT_SEED,
 };
-use crate::payment::{Payment, PaymentState};
 use solana_program::account_info::{next_account_info, AccountInfo};
+use solana_program::bpf_loader_upgradeable::{self, UpgradeableLoaderState};
 use solana_program::clock::Clock;
 use solana_program::entrypoint::ProgramResult;
 use solana_program::hash::{Hash, Hasher};
-use solana_program::program::invoke_signed;
+use solana_program::program::{invoke, invoke_signed, set_return_data};
 use solana_program::program_error::ProgramError;
+use solana_program::program_utils::limited_deserialize;
 use solana_program::pubkey::Pubkey;
+use solana_program::rent::Rent;
 use solana_program::sysvar::Sysvar;
 use solana_program::{system_instruction, system_program};
+use spl_token_2022::extension::non_transferable::NonTransferable;
+use spl_token_2022::extension::permanent_delegate::PermanentDelegate;
+use spl_token_2022::extension::transfer_fee::TransferFeeAmount;
+use spl_token_2022::extension::transfer_hook::TransferHook;
+use spl_token_2022::extension::{BaseStateWithExtensions, StateWithExtensions};
+use spl_token_2022::state::{Account as TokenAccount, Mint};
 
 pub struct SwapFunctions;
 
+struct TokenAccounts<'a, 'b> {
+    user_token_account: &'a AccountInfo<'b>,
+    vault_token_account: &'a AccountInfo<'b>,
+    mint: &'a AccountInfo<'b>,
+    token_program: &'a AccountInfo<'b>,
+}
+
+impl<'a, 'b> TokenAccounts<'a, 'b> {
+    fn next<I: Iterator<Item = &'a AccountInfo<'b>>>(iter: &mut I) -> Result<Self, ProgramError> {
+        Ok(Self {
+            user_token_account: next_account_info(iter)?,
+            vault_token_account: next_account_info(iter)?,
+            mint: next_account_info(iter)?,
+            token_program: next_account_info(iter)?,
+        })
+    }
+    /// Returns the mint decimals required by `transfer_checked`.
+    fn validate(
+        &self,
+        token_program_id: &Pubkey,
+        user: &Pubkey,
+        vault_pda: &Pubkey,
+    ) -> Result<u8, ProgramError> {
+        if *token_program_id != spl_token::id() && *token_program_id != spl_token_2022::id() {
+            return Err(SwapError::NotSupported.into());
+        }
+        if self.token_program.key != token_program_id {
+            return Err(SwapError::InvalidTokenProgram.into());
+        }
+        if self.mint.owner != token_program_id {
+            return Err(SwapError::InvalidMint.into());
+        }
+        let mint_data = self.mint.try_borrow_data()?;
+        let mint =
+            StateWithExtensions::<Mint>::unpack(&mint_data).map_err(|_| SwapError::InvalidMint)?;
+        Self::validate_mint_extensions(&mint)?;
+        Self::validate_token_account(
+            self.user_token_account,
+            token_program_id,
+            self.mint.key,
+            user,
+        )?;
+        Self::validate_token_account(
+            self.vault_token_account,
+            token_program_id,
+            self.mint.key,
+            vault_pda,
+        )?;
+        Ok(mint.base.decimals)
+    }
+    fn validate_mint_extensions(mint: &StateWithExtensions<Mint>) -> ProgramResult {
+        if mint.get_extension::<NonTransferable>().is_ok() {
+            return Err(SwapError::NonTransferableMint.into());
+        }
+        if let Ok(transfer_hook) = mint.get_extension::<TransferHook>() {
+            if Option::<Pubkey>::from(transfer_hook.program_id).is_some() {
+                return Err(SwapError::TransferHookNotSupported.into());
+            }
+        }
+        if let Ok(permanent_delegate) = mint.get_extension::<PermanentDelegate>() {
+            if Option::<Pubkey>::from(permanent_delegate.delegate).is_some() {
+                return Err(SwapError::PermanentDelegateNotSupported.into());
+            }
+        }
+        Ok(())
+    }
+    fn validate_token_account(
+        token_account: &AccountInfo,
+        token_program_id: &Pubkey,
+        mint: &Pubkey,
+        owner: &Pubkey,
+    ) -> ProgramResult {
+        if token_account.owner != token_program_id {
+            return Err(SwapError::InvalidTokenProgram.into());
+        }
+        let data = token_account.try_borrow_data()?;
+        let account = StateWithExtensions::<TokenAccount>::unpack(&data)?;
+        if account.base.mint != *mint {
+            return Err(SwapError::InvalidMint.into());
+        }
+        if account.base.owner != *owner {
+            return Err(SwapError::InvalidTokenAccountOwner.into());
+        }
+        Ok(())
+    }
+    fn vault_balance(&self) -> Result<u64, ProgramError> {
+        let data = self.vault_token_account.try_borrow_data()?;
+        Ok(StateWithExtensions::<TokenAccount>::unpack(&data)?
+            .base
+            .amount)
+    }
+    fn vault_withheld_fees(&self) -> Result<u64, ProgramError> {
+        let data = self.vault_token_account.try_borrow_data()?;
+        let account = StateWithExtensions::<TokenAccount>::unpack(&data)?;
+        Ok(account
+            .get_extension::<TransferFeeAmount>()
+            .map(|fee_amount| u64::from(fee_amount.withheld_amount))
+            .unwrap_or(0))
+    }
+}
+
 impl SwapFunctions {
+    #[allow(clippy::too_many_arguments)]
     fn payment_hash(
         receiver: &Pubkey,
         sender_account: &Pubkey,
         secret_hash: &[u8; 32],
+        secret_hash_algorithm: SecretHashAlgorithm,
         token_program: &Pubkey,
         amount: u64,
+        mint: Option<&Pubkey>,
+        relayer_fee: u64,
+        watcher_reward: u64,
     ) -> Hash {
         let mut hasher = Hasher::default();
         hasher.hash(receiver.as_ref());
@@ -35,6 +151,23 @@ impl SwapFunctions {
         hasher.hash(token_program.as_ref());
         let amount_bytes = amount.to_le_bytes();
         hasher.hash(&amount_bytes);
+        // Lamports payments keep the original hash layout, SPL payments also commit to the mint.
+        if let Some(mint) = mint {
+            hasher.hash(mint.as_ref());
+        }
+        // Committing to the algorithm stops a payment from being spent with a secret whose hash
+        // under another algorithm collides with `secret_hash`. SHA-256 keeps the original layout.
+        if secret_hash_algorithm != SecretHashAlgorithm::Sha256 {
+            hasher.hash(&[secret_hash_algorithm.to_u8()]);
+        }
+        // Payments without a relayer fee or a watcher reward keep the previous layout. The fee is
+        // committed to whenever the reward is, so the two can't be mistaken for each other.
+        if relayer_fee > 0 || watcher_reward > 0 {
+            hasher.hash(&relayer_fee.to_le_bytes());
+        }
+        if watcher_reward > 0 {
+            hasher.hash(&watcher_reward.to_le_bytes());
+        }
         hasher.result()
     }
     fn create_account(
@@ -42,29 +175,55 @@ impl SwapFunctions {
         sender_account: &AccountInfo,
         vault_pda_data: &AccountInfo,
         account_infos: &[AccountInfo],
-        rent_exemption_lamports: u64,
         vault_seeds_data: &[&[u8]],
+        len: usize,
     ) -> ProgramResult {
-        let create_instruction = system_instruction::create_account(
-            sender_account.key,
-            vault_pda_data.key,
-            rent_exemption_lamports,
-            41,
-            program_id,
-        );
-        invoke_signed(&create_instruction, account_infos, &[vault_seeds_data])
-    }
-    fn store_data(vault_pda_data: &AccountInfo, payment: Payment) -> ProgramResult {
-        let data = &mut vault_pda_data.try_borrow_mut_data()?;
-
-        let payment_bytes = payment.pack();
-
-        if data.len() < payment_bytes.len() {
-            return Err(ProgramError::AccountDataTooSmall);
+        let rent_exemption_lamports = Rent::get()?.minimum_balance(len);
+        let current_lamports = vault_pda_data.lamports();
+        if current_lamports == 0 {
+            let create_instruction = system_instruction::create_account(
+                sender_account.key,
+                vault_pda_data.key,
+                rent_exemption_lamports,
+                len as u64,
+                program_id,
+            );
+            return invoke_signed(&create_instruction, account_infos, &[vault_seeds_data]);
         }
 
-        data[..payment_bytes.len()].copy_from_slice(&payment_bytes);
-        Ok(())
+        // Anyone can send lamports to the address in advance, which would make `create_account`
+        // fail, so a pre-funded account is topped up, allocated and assigned instead.
+        let top_up = rent_exemption_lamports.saturating_sub(current_lamports);
+        if top_up > 0 {
+            let transfer_instruction =
+                system_instruction::transfer(sender_account.key, vault_pda_data.key, top_up);
+            invoke(&transfer_instruction, account_infos)?;
+        }
+        let allocate_instruction = system_instruction::allocate(vault_pda_data.key, len as u64);
+        invoke_signed(&allocate_instruction, account_infos, &[vault_seeds_data])?;
+        let assign_instruction = system_instruction::assign(vault_pda_data.key, program_id);
+        invoke_signed(&assign_instruction, account_infos, &[vault_seeds_data])
+    }
+    /// Lock time and terms of the payment in the second account. The full instruction they are
+    /// forwarded to still checks them against the payment hash.
+    fn stored_terms(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+    ) -> Result<(u64, PaymentTerms), ProgramError> {
+        let vault_pda_data = next_account_info(&mut accounts.iter().skip(1))?;
+        if vault_pda_data.owner != program_id {
+            return Err(SwapError::InvalidOwner.into());
+        }
+        let payment = Payment::unpack(
+            &vault_pda_data
+                .try_borrow_data()
+                .map_err(|_| SwapError::SwapAccountNotFound)?,
+        )?;
+        let terms = payment.terms.ok_or(SwapError::PaymentTermsNotStored)?;
+        Ok((payment.lock_time, terms))
+    }
+    fn store_data(vault_pda_data: &AccountInfo, payment: Payment) -> ProgramResult {
+        payment.pack_into(&mut vault_pda_data.try_borrow_mut_data()?)
     }
     fn validate_accounts(
         sender_account: &AccountInfo,
@@ -72,19 +231,19 @@ impl SwapFunctions {
         vault_pda: &AccountInfo,
     ) -> ProgramResult {
         if !sender_account.is_signer {
-            return Err(ProgramError::Custom(SENDER_ACCOUNT_NOT_SIGNER));
+            return Err(SwapError::SenderAccountNotSigner.into());
         }
         if !sender_account.is_writable {
-            return Err(ProgramError::Custom(SENDER_ACCOUNT_NOT_WRITABLE));
+            return Err(SwapError::SenderAccountNotWritable.into());
         }
         if !vault_pda_data.is_writable {
-            return Err(ProgramError::Custom(VAULT_PDA_DATA_NOT_WRITABLE));
+            return Err(SwapError::VaultPdaDataNotWritable.into());
         }
         if !vault_pda.is_writable {
-            return Err(ProgramError::Custom(VAULT_PDA_NOT_WRITABLE));
+            return Err(SwapError::VaultPdaNotWritable.into());
         }
         if vault_pda.owner != &system_program::ID {
-            return Err(ProgramError::Custom(VAULT_PDA_PROGRAM_NOT_OWNER));
+            return Err(SwapError::VaultPdaNotSystemOwned.into());
         }
         Ok(())
     }
@@ -99,48 +258,207 @@ impl SwapFunctions {
             system_instruction::transfer(sender_account.key, vault_pda.key, amount);
         invoke_signed(&transfer_instruction, account_infos, &[vault_seeds])
     }
+    /// Pays the watcher reward held by the vault to whoever submitted a relayed spend or a refund.
+    fn pay_watcher_reward<'a>(
+        vault_pda: &AccountInfo<'a>,
+        submitter: &AccountInfo<'a>,
+        watcher_reward: u64,
+        vault_seeds: &[&[u8]],
+    ) -> ProgramResult {
+        if watcher_reward == 0 {
+            return Ok(());
+        }
+        SwapFunctions::transfer(
+            vault_pda,
+            submitter,
+            &[vault_pda.clone(), submitter.clone()],
+            watcher_reward,
+            vault_seeds,
+        )
+    }
+    fn transfer_tokens<'a>(
+        token_accounts: &TokenAccounts<'_, 'a>,
+        source: &AccountInfo<'a>,
+        destination: &AccountInfo<'a>,
+        authority: &AccountInfo<'a>,
+        amount: u64,
+        decimals: u8,
+        signers_seeds: &[&[&[u8]]],
+    ) -> ProgramResult {
+        let transfer_instruction = spl_token_2022::instruction::transfer_checked(
+            token_accounts.token_program.key,
+            source.key,
+            token_accounts.mint.key,
+            destination.key,
+            authority.key,
+            &[],
+            amount,
+            decimals,
+        )?;
+        invoke_signed(
+            &transfer_instruction,
+            &[
+                source.clone(),
+                token_accounts.mint.clone(),
+                destination.clone(),
+                authority.clone(),
+                token_accounts.token_program.clone(),
+            ],
+            signers_seeds,
+        )
+    }
+    fn close_vault_token_account<'a>(
+        token_accounts: &TokenAccounts<'_, 'a>,
+        vault_pda: &AccountInfo<'a>,
+        rent_payer: &AccountInfo<'a>,
+        decimals: u8,
+        vault_seeds: &[&[u8]],
+    ) -> ProgramResult {
+        // Anything sent to the vault after the swap completed goes back to the sender.
+        let residual = token_accounts.vault_balance()?;
+        if residual > 0 {
+            SwapFunctions::transfer_tokens(
+                token_accounts,
+                token_accounts.vault_token_account,
+                token_accounts.user_token_account,
+                vault_pda,
+                residual,
+                decimals,
+                &[vault_seeds],
+            )?;
+        }
+        // Token-2022 refuses to close accounts holding withheld transfer fees.
+        if token_accounts.vault_withheld_fees()? > 0 {
+            let harvest_instruction =
+                spl_token_2022::extension::transfer_fee::instruction::harvest_withheld_tokens_to_mint(
+                    token_accounts.token_program.key,
+                    token_accounts.mint.key,
+                    &[token_accounts.vault_token_account.key],
+                )?;
+            invoke_signed(
+                &harvest_instruction,
+                &[
+                    token_accounts.mint.clone(),
+                    token_accounts.vault_token_account.clone(),
+                    token_accounts.token_program.clone(),
+                ],
+                &[],
+            )?;
+        }
+        let close_instruction = spl_token_2022::instruction::close_account(
+            token_accounts.token_program.key,
+            token_accounts.vault_token_account.key,
+            rent_payer.key,
+            vault_pda.key,
+            &[],
+        )?;
+        invoke_signed(
+            &close_instruction,
+            &[
+                token_accounts.vault_token_account.clone(),
+                rent_payer.clone(),
+                vault_pda.clone(),
+                token_accounts.token_program.clone(),
+            ],
+            &[vault_seeds],
+        )
+    }
+    fn close_data_account(vault_pda_data: &AccountInfo, rent_payer: &AccountInfo) -> ProgramResult {
+        vault_pda_data.try_borrow_mut_data()?.fill(0);
+        vault_pda_data.realloc(0, false)?;
+        vault_pda_data.assign(&system_program::ID);
+
+        let lamports = vault_pda_data.lamports();
+        **vault_pda_data.try_borrow_mut_lamports()? = 0;
+        **rent_payer.try_borrow_mut_lamports()? += lamports;
+        Ok(())
+    }
     pub fn lamports_payment(
         program_id: &Pubkey,
         accounts: &[AccountInfo],
         params: LamportsPaymentParams,
     ) -> ProgramResult {
         if params.receiver == Pubkey::default() {
-            return Err(ProgramError::Custom(RECEIVER_SET_TO_DEFAULT));
+            return Err(SwapError::ReceiverSetToDefault.into());
         }
         if params.amount == 0 {
-            return Err(ProgramError::Custom(AMOUNT_ZERO));
+            return Err(SwapError::AmountZero.into());
+        }
+        if params.spend_deadline > params.lock_time {
+            return Err(SwapError::InvalidSpendDeadline.into());
+        }
+        if params.relayer_fee >= params.amount {
+            return Err(SwapError::InvalidRelayerFee.into());
         }
         let accounts_iter = &mut accounts.iter();
         let sender_account = next_account_info(accounts_iter)?;
         let vault_pda_data = next_account_info(accounts_iter)?;
         let vault_pda = next_account_info(accounts_iter)?;
+        let _system_program = next_account_info(accounts_iter)?;
+        let config = Config::load(program_id, next_account_info(accounts_iter)?)?;
 
         SwapFunctions::validate_accounts(sender_account, vault_pda_data, vault_pda)?;
+        if config.paused {
+            return Err(SwapError::PaymentsPaused.into());
+        }
+        if params.amount < config.settings.min_amount {
+            return Err(SwapError::AmountBelowMinimum.into());
+        }
 
-        let vault_seeds: &[&[u8]] = &[
-            b"swap",
-            &params.lock_time.to_le_bytes()[..],
-            &params.secret_hash[..],
-            &[params.vault_bump_seed],
-        ];
-        let vault_seeds_data: &[&[u8]] = &[
-            b"swap_data",
-            &params.lock_time.to_le_bytes()[..],
-            &params.secret_hash[..],
-            &[params.vault_bump_seed_data],
-        ];
+        let payment_seeds = PaymentSeeds::new(
+            params.lock_time,
+            &params.secret_hash,
+            sender_account.key,
+            &params.receiver,
+            None,
+        );
+        payment_seeds.verify_vault_addresses(
+            program_id,
+            SeedScheme::Bound,
+            vault_pda.key,
+            params.vault_bump_seed,
+            vault_pda_data.key,
+            params.vault_bump_seed_data,
+        )?;
+        let vault_bump = [params.vault_bump_seed];
+        let vault_seeds = payment_seeds.seeds(VAULT_SEED, SeedScheme::Bound, &vault_bump);
+        let vault_bump_data = [params.vault_bump_seed_data];
+        let vault_seeds_data =
+            payment_seeds.seeds(VAULT_DATA_SEED, SeedScheme::Bound, &vault_bump_data);
 
         let payment_hash = SwapFunctions::payment_hash(
             &params.receiver,
             sender_account.key,
             &params.secret_hash,
+            params.secret_hash_algorithm,
             &Pubkey::new_from_array([0; 32]),
             params.amount,
+            None,
+            params.relayer_fee,
+            params.watcher_reward,
         );
         let payment = Payment {
             payment_hash: payment_hash.to_bytes(),
             lock_time: params.lock_time,
             state: PaymentState::PaymentSent,
+            secret_hash_algorithm: params.secret_hash_algorithm,
+            lock_time_kind: params.lock_time_kind,
+            spend_deadline: params.spend_deadline,
+            terms: Some(PaymentTerms {
+                sender: *sender_account.key,
+                receiver: params.receiver,
+                mint: Pubkey::default(),
+                token_program: Pubkey::default(),
+                secret_hash: params.secret_hash,
+                amount: params.amount,
+                vault_bump_seed: params.vault_bump_seed,
+                vault_bump_seed_data: params.vault_bump_seed_data,
+                created_at: Clock::get()?.unix_timestamp,
+            }),
+            secret: None,
+            relayer_fee: params.relayer_fee,
+            watcher_reward: params.watcher_reward,
+            dex_fee: params.dex_fee,
         };
 
         SwapFunctions::create_account(
@@ -148,19 +466,39 @@ impl SwapFunctions {
             sender_account,
             vault_pda_data,
             &[sender_account.clone(), vault_pda_data.clone()],
-            params.rent_exemption_lamports,
-            vault_seeds_data,
+            &vault_seeds_data,
+            Payment::LEN,
         )?;
 
         SwapFunctions::store_data(vault_pda_data, payment)?;
 
+        // The vault keeps no data, it only has to stay rent exempt once the amount is paid out.
+        let vault_rent_exemption_lamports = Rent::get()?
+            .minimum_balance(0)
+            .saturating_sub(vault_pda.lamports());
         SwapFunctions::transfer(
             sender_account,
             vault_pda,
             &[sender_account.clone(), vault_pda.clone()],
-            params.amount + params.rent_exemption_lamports,
-            vault_seeds,
-        )
+            params
+                .amount
+                .checked_add(params.watcher_reward)
+                .and_then(|lamports| lamports.checked_add(vault_rent_exemption_lamports))
+                .ok_or(ProgramError::ArithmeticOverflow)?,
+            &vault_seeds,
+        )?;
+
+        SwapEvent {
+            kind: SwapEventKind::PaymentSent {
+                dex_fee: params.dex_fee,
+            },
+            payment: *vault_pda_data.key,
+            payment_hash: payment_hash.to_bytes(),
+            amount: params.amount,
+            mint: Pubkey::default(),
+        }
+        .emit();
+        Ok(())
     }
     pub fn spl_token_payment(
         program_id: &Pubkey,
@@ -168,37 +506,122 @@ impl SwapFunctions {
         params: SPLTokenPaymentParams,
     ) -> ProgramResult {
         if params.receiver == Pubkey::default() {
-            return Err(ProgramError::Custom(RECEIVER_SET_TO_DEFAULT));
+            return Err(SwapError::ReceiverSetToDefault.into());
         }
         if params.amount == 0 {
-            return Err(ProgramError::Custom(AMOUNT_ZERO));
+            return Err(SwapError::AmountZero.into());
+        }
+        if params.spend_deadline > params.lock_time {
+            return Err(SwapError::InvalidSpendDeadline.into());
+        }
+        if params.relayer_fee >= params.amount {
+            return Err(SwapError::InvalidRelayerFee.into());
         }
         let accounts_iter = &mut accounts.iter();
         let sender_account = next_account_info(accounts_iter)?;
         let vault_pda_data = next_account_info(accounts_iter)?;
         let vault_pda = next_account_info(accounts_iter)?;
+        let token_accounts = TokenAccounts::next(accounts_iter)?;
+        let _system_program = next_account_info(accounts_iter)?;
+        let config = Config::load(program_id, next_account_info(accounts_iter)?)?;
 
         SwapFunctions::validate_accounts(sender_account, vault_pda_data, vault_pda)?;
+        if config.paused {
+            return Err(SwapError::PaymentsPaused.into());
+        }
+        if params.amount < config.settings.min_amount {
+            return Err(SwapError::AmountBelowMinimum.into());
+        }
+        // Only new payments are bound by the allowed token programs, so that existing ones can
+        // always be spent or refunded.
+        if !config
+            .settings
+            .allowed_token_programs
+            .contains(&params.token_program)
+        {
+            return Err(SwapError::TokenProgramNotAllowed.into());
+        }
+        let decimals =
+            token_accounts.validate(&params.token_program, sender_account.key, vault_pda.key)?;
 
-        let vault_seeds_data: &[&[u8]] = &[
-            b"swap_data",
-            &params.lock_time.to_le_bytes()[..],
-            &params.secret_hash[..],
-            &[params.vault_bump_seed_data],
-        ];
+        let payment_seeds = PaymentSeeds::new(
+            params.lock_time,
+            &params.secret_hash,
+            sender_account.key,
+            &params.receiver,
+            Some(token_accounts.mint.key),
+        );
+        payment_seeds.verify_vault_addresses(
+            program_id,
+            SeedScheme::Bound,
+            vault_pda.key,
+            params.vault_bump_seed,
+            vault_pda_data.key,
+            params.vault_bump_seed_data,
+        )?;
+        let vault_bump = [params.vault_bump_seed];
+        let vault_seeds = payment_seeds.seeds(VAULT_SEED, SeedScheme::Bound, &vault_bump);
+        let vault_bump_data = [params.vault_bump_seed_data];
+        let vault_seeds_data =
+            payment_seeds.seeds(VAULT_DATA_SEED, SeedScheme::Bound, &vault_bump_data);
+
+        // Transfer-fee mints credit the vault with less than was sent, so the payment
+        // commits to the amount actually held in escrow.
+        let vault_balance = token_accounts.vault_balance()?;
+        SwapFunctions::transfer_tokens(
+            &token_accounts,
+            token_accounts.user_token_account,
+            token_accounts.vault_token_account,
+            sender_account,
+            params.amount,
+            decimals,
+            &[],
+        )?;
+        let amount = token_accounts
+            .vault_balance()?
+            .checked_sub(vault_balance)
+            .ok_or(ProgramError::ArithmeticOverflow)?;
+        if amount == 0 {
+            return Err(SwapError::AmountZero.into());
+        }
+        if params.relayer_fee >= amount {
+            return Err(SwapError::InvalidRelayerFee.into());
+        }
 
         let payment_hash = SwapFunctions::payment_hash(
             &params.receiver,
             sender_account.key,
             &params.secret_hash,
+            params.secret_hash_algorithm,
             &params.token_program,
-            params.amount,
+            amount,
+            Some(token_accounts.mint.key),
+            params.relayer_fee,
+            params.watcher_reward,
         );
 
         let payment = Payment {
             payment_hash: payment_hash.to_bytes(),
             lock_time: params.lock_time,
             state: PaymentState::PaymentSent,
+            secret_hash_algorithm: params.secret_hash_algorithm,
+            lock_time_kind: params.lock_time_kind,
+            spend_deadline: params.spend_deadline,
+            terms: Some(PaymentTerms {
+                sender: *sender_account.key,
+                receiver: params.receiver,
+                mint: *token_accounts.mint.key,
+                token_program: params.token_program,
+                secret_hash: params.secret_hash,
+                amount,
+                vault_bump_seed: params.vault_bump_seed,
+                vault_bump_seed_data: params.vault_bump_seed_data,
+                created_at: Clock::get()?.unix_timestamp,
+            }),
+            secret: None,
+            relayer_fee: params.relayer_fee,
+            watcher_reward: params.watcher_reward,
+            dex_fee: params.dex_fee,
         };
 
         SwapFunctions::create_account(
@@ -206,14 +629,235 @@ impl SwapFunctions {
             sender_account,
             vault_pda_data,
             &[sender_account.clone(), vault_pda_data.clone()],
-            params.rent_exemption_lamports,
-            vault_seeds_data,
+            &vault_seeds_data,
+            Payment::LEN,
         )?;
 
         SwapFunctions::store_data(vault_pda_data, payment)?;
 
+        // The watcher reward is paid in lamports, which the vault holds next to its rent exemption.
+        if params.watcher_reward > 0 {
+            let vault_rent_exemption_lamports = Rent::get()?
+                .minimum_balance(0)
+                .saturating_sub(vault_pda.lamports());
+            SwapFunctions::transfer(
+                sender_account,
+                vault_pda,
+                &[sender_account.clone(), vault_pda.clone()],
+                params
+                    .watcher_reward
+                    .checked_add(vault_rent_exemption_lamports)
+                    .ok_or(ProgramError::ArithmeticOverflow)?,
+                &vault_seeds,
+            )?;
+        }
+
+        SwapEvent {
+            kind: SwapEventKind::PaymentSent {
+                dex_fee: params.dex_fee,
+            },
+            payment: *vault_pda_data.key,
+            payment_hash: payment_hash.to_bytes(),
+            amount,
+            mint: *token_accounts.mint.key,
+        }
+        .emit();
         Ok(())
     }
+    /// Sends the dex fee of a payment of `amount` to the dex fee account following its accounts,
+    /// in the payment's currency. `token_program` is the default pubkey for lamports payments.
+    pub fn pay_dex_fee(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        dex_fee: u64,
+        amount: u64,
+        token_program: &Pubkey,
+    ) -> ProgramResult {
+        let accounts_iter = &mut accounts.iter();
+        let sender_account = next_account_info(accounts_iter)?;
+        let _vault_pda_data = next_account_info(accounts_iter)?;
+        let vault_pda = next_account_info(accounts_iter)?;
+        let token_accounts = if *token_program == Pubkey::new_from_array([0; 32]) {
+            None
+        } else {
+            Some(TokenAccounts::next(accounts_iter)?)
+        };
+        let _system_program = next_account_info(accounts_iter)?;
+        let config = Config::load(program_id, next_account_info(accounts_iter)?)?;
+        let dex_fee_account = next_account_info(accounts_iter)?;
+
+        if !sender_account.is_signer {
+            return Err(SwapError::SenderAccountNotSigner.into());
+        }
+        let configured_account = &config.settings.dex_fee_account;
+        if *configured_account == Pubkey::default() {
+            return Err(SwapError::InvalidDexFeeAccount.into());
+        }
+        if dex_fee < config.settings.min_dex_fee(amount) {
+            return Err(SwapError::DexFeeTooLow.into());
+        }
+        match token_accounts {
+            None => {
+                if dex_fee_account.key != configured_account {
+                    return Err(SwapError::InvalidDexFeeAccount.into());
+                }
+                invoke(
+                    &system_instruction::transfer(sender_account.key, dex_fee_account.key, dex_fee),
+                    &[sender_account.clone(), dex_fee_account.clone()],
+                )
+            }
+            Some(token_accounts) => {
+                let decimals =
+                    token_accounts.validate(token_program, sender_account.key, vault_pda.key)?;
+                TokenAccounts::validate_token_account(
+                    dex_fee_account,
+                    token_program,
+                    token_accounts.mint.key,
+                    configured_account,
+                )
+                .map_err(|_| SwapError::InvalidDexFeeAccount)?;
+                SwapFunctions::transfer_tokens(
+                    &token_accounts,
+                    token_accounts.user_token_account,
+                    dex_fee_account,
+                    sender_account,
+                    dex_fee,
+                    decimals,
+                    &[],
+                )
+            }
+        }
+    }
+    /// Creates the config account, which only the program's upgrade authority can do so that
+    /// nobody can claim it first.
+    pub fn initialize_config(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        admin: Pubkey,
+        settings: ConfigSettings,
+    ) -> ProgramResult {
+        let accounts_iter = &mut accounts.iter();
+        let upgrade_authority = next_account_info(accounts_iter)?;
+        let config_account = next_account_info(accounts_iter)?;
+        let program_data = next_account_info(accounts_iter)?;
+        let admin_account = next_account_info(accounts_iter)?;
+
+        if !upgrade_authority.is_signer {
+            return Err(SwapError::UnauthorizedConfigUpdate.into());
+        }
+        // The admin signs too, so that the config can't be created with a key nobody holds.
+        if admin == Pubkey::default() {
+            return Err(SwapError::InvalidConfigAdmin.into());
+        }
+        if !admin_account.is_signer || *admin_account.key != admin {
+            return Err(SwapError::UnauthorizedConfigUpdate.into());
+        }
+        let (program_data_address, _) =
+            Pubkey::find_program_address(&[program_id.as_ref()], &bpf_loader_upgradeable::id());
+        if *program_data.key != program_data_address
+            || *program_data.owner != bpf_loader_upgradeable::id()
+        {
+            return Err(SwapError::UnauthorizedConfigUpdate.into());
+        }
+        let program_data_state = limited_deserialize(
+            &program_data.try_borrow_data()?,
+            UpgradeableLoaderState::size_of_programdata_metadata() as u64,
+        )
+        .map_err(|_| ProgramError::InvalidAccountData)?;
+        match program_data_state {
+            UpgradeableLoaderState::ProgramData {
+                upgrade_authority_address: Some(authority),
+                ..
+            } if authority == *upgrade_authority.key => {}
+            _ => return Err(SwapError::UnauthorizedConfigUpdate.into()),
+        }
+        settings.validate()?;
+
+        let (config_address, config_bump) = find_config_address(program_id);
+        if *config_account.key != config_address || config_account.owner == program_id {
+            return Err(SwapError::InvalidConfigAccount.into());
+        }
+        SwapFunctions::create_account(
+            program_id,
+            upgrade_authority,
+            config_account,
+            &[upgrade_authority.clone(), config_account.clone()],
+            &[CONFIG_SEED, &[config_bump]],
+            Config::LEN,
+        )?;
+        Config {
+            admin,
+            pending_admin: Pubkey::default(),
+            settings,
+            paused: false,
+        }
+        .pack_into_account(config_account)
+    }
+    pub fn update_config(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        settings: ConfigSettings,
+    ) -> ProgramResult {
+        settings.validate()?;
+        let (config_account, mut config) = SwapFunctions::admin_config(program_id, accounts)?;
+        config.settings = settings;
+        config.pack_into_account(config_account)
+    }
+    pub fn propose_config_admin(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        admin: Pubkey,
+    ) -> ProgramResult {
+        if admin == Pubkey::default() {
+            return Err(SwapError::InvalidConfigAdmin.into());
+        }
+        let (config_account, mut config) = SwapFunctions::admin_config(program_id, accounts)?;
+        config.pending_admin = admin;
+        config.pack_into_account(config_account)
+    }
+    pub fn accept_config_admin(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+        let (config_account, mut config) =
+            SwapFunctions::signed_config(program_id, accounts, |config| config.pending_admin)?;
+        config.admin = config.pending_admin;
+        config.pending_admin = Pubkey::default();
+        config.pack_into_account(config_account)
+    }
+    pub fn set_paused(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        paused: bool,
+    ) -> ProgramResult {
+        let (config_account, mut config) = SwapFunctions::admin_config(program_id, accounts)?;
+        config.paused = paused;
+        config.pack_into_account(config_account)
+    }
+    /// Initialized config in the second account, checking that the first one is its admin and
+    /// signed.
+    fn admin_config<'a, 'b>(
+        program_id: &Pubkey,
+        accounts: &'a [AccountInfo<'b>],
+    ) -> Result<(&'a AccountInfo<'b>, Config), ProgramError> {
+        SwapFunctions::signed_config(program_id, accounts, |config| config.admin)
+    }
+    /// Initialized config in the second account, checking that the first one is the `authority`
+    /// it names and signed.
+    fn signed_config<'a, 'b>(
+        program_id: &Pubkey,
+        accounts: &'a [AccountInfo<'b>],
+        authority: fn(&Config) -> Pubkey,
+    ) -> Result<(&'a AccountInfo<'b>, Config), ProgramError> {
+        let accounts_iter = &mut accounts.iter();
+        let signer = next_account_info(accounts_iter)?;
+        let config_account = next_account_info(accounts_iter)?;
+        if config_account.owner != program_id {
+            return Err(SwapError::InvalidConfigAccount.into());
+        }
+        let config = Config::load(program_id, config_account)?;
+        if !signer.is_signer || *signer.key != authority(&config) {
+            return Err(SwapError::UnauthorizedConfigUpdate.into());
+        }
+        Ok((config_account, config))
+    }
     pub fn receiver_spend(
         program_id: &Pubkey,
         accounts: &[AccountInfo],
@@ -223,59 +867,257 @@ impl SwapFunctions {
         let receiver_account = next_account_info(accounts_iter)?;
         let vault_pda_data = next_account_info(accounts_iter)?;
         let vault_pda = next_account_info(accounts_iter)?;
+        let token_accounts = if params.token_program == Pubkey::new_from_array([0; 32]) {
+            None
+        } else {
+            Some(TokenAccounts::next(accounts_iter)?)
+        };
 
         SwapFunctions::validate_accounts(receiver_account, vault_pda_data, vault_pda)?;
+        SwapFunctions::spend(
+            program_id,
+            receiver_account,
+            vault_pda_data,
+            vault_pda,
+            token_accounts,
+            None,
+            params,
+        )
+    }
+    /// Spends a payment on behalf of the receiver account following the vault, paying the relayer
+    /// fee to the signer submitting it, so that a receiver without lamports can be paid.
+    pub fn relayed_receiver_spend(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        params: ReceiverSpendParams,
+    ) -> ProgramResult {
+        let accounts_iter = &mut accounts.iter();
+        let relayer_account = next_account_info(accounts_iter)?;
+        let vault_pda_data = next_account_info(accounts_iter)?;
+        let vault_pda = next_account_info(accounts_iter)?;
+        let receiver_account = next_account_info(accounts_iter)?;
+        let (token_accounts, relayer_token_account) =
+            if params.token_program == Pubkey::new_from_array([0; 32]) {
+                (None, None)
+            } else {
+                let token_accounts = TokenAccounts::next(accounts_iter)?;
+                // The relayer token account ahead of the system program is only required by
+                // payments with a relayer fee.
+                let relayer_token_account = match accounts_iter.len() {
+                    0 | 1 => None,
+                    _ => Some(next_account_info(accounts_iter)?),
+                };
+                (Some(token_accounts), relayer_token_account)
+            };
+
+        SwapFunctions::validate_accounts(relayer_account, vault_pda_data, vault_pda)?;
+        SwapFunctions::spend(
+            program_id,
+            receiver_account,
+            vault_pda_data,
+            vault_pda,
+            token_accounts,
+            Some((relayer_account, relayer_token_account)),
+            params,
+        )
+    }
+    /// Pays the payment out to `receiver_account`, minus the relayer fee when `relayer` submitted
+    /// the spend. `relayer` holds the relayer's token account for SPL token payments, which is only
+    /// required when the payment has a relayer fee.
+    fn spend<'a>(
+        program_id: &Pubkey,
+        receiver_account: &AccountInfo<'a>,
+        vault_pda_data: &AccountInfo<'a>,
+        vault_pda: &AccountInfo<'a>,
+        token_accounts: Option<TokenAccounts<'_, 'a>>,
+        relayer: Option<(&AccountInfo<'a>, Option<&AccountInfo<'a>>)>,
+        params: ReceiverSpendParams,
+    ) -> ProgramResult {
+        let decimals = match &token_accounts {
+            Some(token_accounts) => token_accounts.validate(
+                &params.token_program,
+                receiver_account.key,
+                vault_pda.key,
+            )?,
+            None => 0,
+        };
+        if let (Some(token_accounts), Some((relayer_account, Some(relayer_token_account)))) =
+            (&token_accounts, relayer)
+        {
+            TokenAccounts::validate_token_account(
+                relayer_token_account,
+                &params.token_program,
+                token_accounts.mint.key,
+                relayer_account.key,
+            )?;
+        }
 
         if vault_pda_data.owner != program_id {
-            return Err(ProgramError::Custom(INVALID_OWNER));
+            return Err(SwapError::InvalidOwner.into());
         }
 
-        let mut hasher = Hasher::default();
-        hasher.hash(&params.secret);
-        let secret_hash = hasher.result();
+        let swap_account_data = &mut vault_pda_data
+            .try_borrow_mut_data()
+            .map_err(|_| SwapError::SwapAccountNotFound)?;
+        let mut swap_payment = Payment::unpack(swap_account_data)?;
 
-        let vault_seeds: &[&[u8]] = &[
-            b"swap",
-            &params.lock_time.to_le_bytes()[..],
-            &secret_hash.to_bytes()[..],
-            &[params.vault_bump_seed],
-        ];
+        let secret_hash = swap_payment
+            .secret_hash_algorithm
+            .hash_secret(&params.secret);
+        let payment_seeds = PaymentSeeds::new(
+            params.lock_time,
+            &secret_hash,
+            &params.sender,
+            receiver_account.key,
+            token_accounts.as_ref().map(|accounts| accounts.mint.key),
+        );
+        let scheme =
+            payment_seeds.scheme(program_id, vault_pda_data.key, params.vault_bump_seed_data);
+        payment_seeds.verify_vault_addresses(
+            program_id,
+            scheme,
+            vault_pda.key,
+            params.vault_bump_seed,
+            vault_pda_data.key,
+            params.vault_bump_seed_data,
+        )?;
+        let vault_bump = [params.vault_bump_seed];
+        let vault_seeds = payment_seeds.seeds(VAULT_SEED, scheme, &vault_bump);
 
         let payment_hash = SwapFunctions::payment_hash(
             receiver_account.key,
             &params.sender,
-            &secret_hash.to_bytes(),
+            &secret_hash,
+            swap_payment.secret_hash_algorithm,
             &params.token_program,
             params.amount,
+            token_accounts.as_ref().map(|accounts| accounts.mint.key),
+            swap_payment.relayer_fee,
+            swap_payment.watcher_reward,
         );
 
-        let swap_account_data = &mut vault_pda_data
-            .try_borrow_mut_data()
-            .map_err(|_| ProgramError::Custom(SWAP_ACCOUNT_NOT_FOUND))?;
-        let mut swap_payment = Payment::unpack(swap_account_data)?;
         if swap_payment.payment_hash != payment_hash.to_bytes() {
-            return Err(ProgramError::Custom(INVALID_PAYMENT_HASH));
+            return Err(SwapError::InvalidPaymentHash.into());
         }
         if swap_payment.state != PaymentState::PaymentSent {
-            return Err(ProgramError::Custom(INVALID_PAYMENT_STATE));
+            return Err(SwapError::InvalidPaymentState.into());
+        }
+        if swap_payment
+            .lock_time_kind
+            .has_passed(swap_payment.spend_deadline, &Clock::get()?)
+        {
+            return Err(SwapError::SpendDeadlinePassed.into());
         }
 
         swap_payment.state = PaymentState::ReceiverSpent;
-        let payment_bytes = swap_payment.pack();
-
-        swap_account_data[..payment_bytes.len()].copy_from_slice(&payment_bytes);
+        // v1 accounts have no room for the secret.
+        if swap_payment.terms.is_some() {
+            swap_payment.secret = Some(params.secret.clone());
+        }
+        swap_payment.pack_into(swap_account_data)?;
 
-        if params.token_program == Pubkey::new_from_array([0; 32]) {
-            SwapFunctions::transfer(
+        // The receiver spending the payment itself keeps the relayer fee.
+        let relayer_fee = match relayer {
+            Some(_) => swap_payment.relayer_fee,
+            None => 0,
+        };
+        if let (Some(_), Some((_, None))) = (&token_accounts, relayer) {
+            if relayer_fee > 0 {
+                return Err(ProgramError::NotEnoughAccountKeys);
+            }
+        }
+        let receiver_amount = params
+            .amount
+            .checked_sub(relayer_fee)
+            .ok_or(SwapError::InvalidRelayerFee)?;
+        let mint = match &token_accounts {
+            None => {
+                SwapFunctions::transfer(
+                    vault_pda,
+                    receiver_account,
+                    &[vault_pda.clone(), receiver_account.clone()],
+                    receiver_amount,
+                    &vault_seeds,
+                )?;
+                if let Some((relayer_account, _)) = relayer.filter(|_| relayer_fee > 0) {
+                    SwapFunctions::transfer(
+                        vault_pda,
+                        relayer_account,
+                        &[vault_pda.clone(), relayer_account.clone()],
+                        relayer_fee,
+                        &vault_seeds,
+                    )?;
+                }
+                Pubkey::default()
+            }
+            Some(token_accounts) => {
+                SwapFunctions::transfer_tokens(
+                    token_accounts,
+                    token_accounts.vault_token_account,
+                    token_accounts.user_token_account,
+                    vault_pda,
+                    receiver_amount,
+                    decimals,
+                    &[&vault_seeds],
+                )?;
+                if let Some((_, Some(relayer_token_account))) = relayer.filter(|_| relayer_fee > 0)
+                {
+                    SwapFunctions::transfer_tokens(
+                        token_accounts,
+                        token_accounts.vault_token_account,
+                        relayer_token_account,
+                        vault_pda,
+                        relayer_fee,
+                        decimals,
+                        &[&vault_seeds],
+                    )?;
+                }
+                *token_accounts.mint.key
+            }
+        };
+        // A receiver spending the payment itself isn't owed the reward, which stays in the vault
+        // until `close_payment` returns it to the sender.
+        if let Some((relayer_account, _)) = relayer {
+            SwapFunctions::pay_watcher_reward(
                 vault_pda,
-                receiver_account,
-                &[vault_pda.clone(), receiver_account.clone()],
-                params.amount,
-                vault_seeds,
-            )
-        } else {
-            Err(ProgramError::Custom(NOT_SUPPORTED))
+                relayer_account,
+                swap_payment.watcher_reward,
+                &vault_seeds,
+            )?;
         }
+
+        SwapEvent {
+            kind: SwapEventKind::ReceiverSpent {
+                secret: params.secret,
+            },
+            payment: *vault_pda_data.key,
+            payment_hash: swap_payment.payment_hash,
+            amount: params.amount,
+            mint,
+        }
+        .emit();
+        Ok(())
+    }
+    /// Spends a v2 payment with the parameters stored in its account.
+    pub fn compact_receiver_spend(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        secret: Vec<u8>,
+    ) -> ProgramResult {
+        let (lock_time, terms) = SwapFunctions::stored_terms(program_id, accounts)?;
+        SwapFunctions::receiver_spend(
+            program_id,
+            accounts,
+            ReceiverSpendParams {
+                secret,
+                lock_time,
+                amount: terms.amount,
+                sender: terms.sender,
+                token_program: terms.token_program,
+                vault_bump_seed: terms.vault_bump_seed,
+                vault_bump_seed_data: terms.vault_bump_seed_data,
+            },
+        )
     }
     pub fn sender_refund(
         program_id: &Pubkey,
@@ -286,60 +1128,313 @@ impl SwapFunctions {
         let sender_account = next_account_info(accounts_iter)?;
         let vault_pda_data = next_account_info(accounts_iter)?;
         let vault_pda = next_account_info(accounts_iter)?;
+        let token_accounts = if params.token_program == Pubkey::new_from_array([0; 32]) {
+            None
+        } else {
+            Some(TokenAccounts::next(accounts_iter)?)
+        };
 
         SwapFunctions::validate_accounts(sender_account, vault_pda_data, vault_pda)?;
+        SwapFunctions::refund(
+            program_id,
+            sender_account,
+            sender_account,
+            vault_pda_data,
+            vault_pda,
+            token_accounts,
+            params,
+        )
+    }
+    /// Refunds a payment to the sender account following the vault once its lock time has passed,
+    /// so that watchers can refund senders that are offline.
+    pub fn relayed_sender_refund(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        params: SenderRefundParams,
+    ) -> ProgramResult {
+        let accounts_iter = &mut accounts.iter();
+        let relayer_account = next_account_info(accounts_iter)?;
+        let vault_pda_data = next_account_info(accounts_iter)?;
+        let vault_pda = next_account_info(accounts_iter)?;
+        let sender_account = next_account_info(accounts_iter)?;
+        let token_accounts = if params.token_program == Pubkey::new_from_array([0; 32]) {
+            None
+        } else {
+            Some(TokenAccounts::next(accounts_iter)?)
+        };
 
-        let vault_seeds: &[&[u8]] = &[
-            b"swap",
-            &params.lock_time.to_le_bytes()[..],
-            &params.secret_hash[..],
-            &[params.vault_bump_seed],
-        ];
+        SwapFunctions::validate_accounts(relayer_account, vault_pda_data, vault_pda)?;
+        SwapFunctions::refund(
+            program_id,
+            relayer_account,
+            sender_account,
+            vault_pda_data,
+            vault_pda,
+            token_accounts,
+            params,
+        )
+    }
+    /// Pays the payment back to `sender_account`, the payment hash binding it to the sender, and
+    /// the watcher reward to `submitter`.
+    fn refund<'a>(
+        program_id: &Pubkey,
+        submitter: &AccountInfo<'a>,
+        sender_account: &AccountInfo<'a>,
+        vault_pda_data: &AccountInfo<'a>,
+        vault_pda: &AccountInfo<'a>,
+        token_accounts: Option<TokenAccounts<'_, 'a>>,
+        params: SenderRefundParams,
+    ) -> ProgramResult {
+        let decimals = match &token_accounts {
+            Some(token_accounts) => {
+                token_accounts.validate(&params.token_program, sender_account.key, vault_pda.key)?
+            }
+            None => 0,
+        };
+
+        let payment_seeds = PaymentSeeds::new(
+            params.lock_time,
+            &params.secret_hash,
+            sender_account.key,
+            &params.receiver,
+            token_accounts.as_ref().map(|accounts| accounts.mint.key),
+        );
+        let scheme =
+            payment_seeds.scheme(program_id, vault_pda_data.key, params.vault_bump_seed_data);
+        payment_seeds.verify_vault_addresses(
+            program_id,
+            scheme,
+            vault_pda.key,
+            params.vault_bump_seed,
+            vault_pda_data.key,
+            params.vault_bump_seed_data,
+        )?;
+        let vault_bump = [params.vault_bump_seed];
+        let vault_seeds = payment_seeds.seeds(VAULT_SEED, scheme, &vault_bump);
 
         if vault_pda_data.owner != program_id {
-            return Err(ProgramError::Custom(INVALID_OWNER));
+            return Err(SwapError::InvalidOwner.into());
         }
 
+        let swap_account_data = &mut vault_pda_data
+            .try_borrow_mut_data()
+            .map_err(|_| SwapError::SwapAccountNotFound)?;
+        let mut swap_payment = Payment::unpack(swap_account_data)?;
         let payment_hash = SwapFunctions::payment_hash(
             &params.receiver,
             sender_account.key,
             &params.secret_hash,
+            swap_payment.secret_hash_algorithm,
             &params.token_program,
             params.amount,
+            token_accounts.as_ref().map(|accounts| accounts.mint.key),
+            swap_payment.relayer_fee,
+            swap_payment.watcher_reward,
         );
 
-        let swap_account_data = &mut vault_pda_data
-            .try_borrow_mut_data()
-            .map_err(|_| ProgramError::Custom(SWAP_ACCOUNT_NOT_FOUND))?;
-        let mut swap_payment = Payment::unpack(swap_account_data)?;
-
-        let clock = Clock::get()?;
-        let now = clock.unix_timestamp as u64;
-
         if swap_payment.payment_hash != payment_hash.to_bytes() {
-            return Err(ProgramError::Custom(INVALID_PAYMENT_HASH));
+            return Err(SwapError::InvalidPaymentHash.into());
         }
         if swap_payment.state != PaymentState::PaymentSent {
-            return Err(ProgramError::Custom(INVALID_PAYMENT_STATE));
+            return Err(SwapError::InvalidPaymentState.into());
         }
-        if swap_payment.lock_time >= now {
-            return Err(ProgramError::Custom(WAIT_FOR_LOCK_TIME));
+        if !swap_payment
+            .lock_time_kind
+            .has_passed(swap_payment.lock_time, &Clock::get()?)
+        {
+            return Err(SwapError::WaitForLockTime.into());
         }
         swap_payment.state = PaymentState::SenderRefunded;
-        let payment_bytes = swap_payment.pack();
+        swap_payment.pack_into(swap_account_data)?;
 
-        swap_account_data[..payment_bytes.len()].copy_from_slice(&payment_bytes);
+        let mint = match &token_accounts {
+            None => {
+                SwapFunctions::transfer(
+                    vault_pda,
+                    sender_account,
+                    &[vault_pda.clone(), sender_account.clone()],
+                    params.amount,
+                    &vault_seeds,
+                )?;
+                Pubkey::default()
+            }
+            Some(token_accounts) => {
+                SwapFunctions::transfer_tokens(
+                    token_accounts,
+                    token_accounts.vault_token_account,
+                    token_accounts.user_token_account,
+                    vault_pda,
+                    params.amount,
+                    decimals,
+                    &[&vault_seeds],
+                )?;
+                *token_accounts.mint.key
+            }
+        };
+        SwapFunctions::pay_watcher_reward(
+            vault_pda,
+            submitter,
+            swap_payment.watcher_reward,
+            &vault_seeds,
+        )?;
 
-        if params.token_program == Pubkey::new_from_array([0; 32]) {
+        SwapEvent {
+            kind: SwapEventKind::SenderRefunded,
+            payment: *vault_pda_data.key,
+            payment_hash: swap_payment.payment_hash,
+            amount: params.amount,
+            mint,
+        }
+        .emit();
+        Ok(())
+    }
+    /// Refunds a v2 payment with the parameters stored in its account.
+    pub fn compact_sender_refund(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+        let (lock_time, terms) = SwapFunctions::stored_terms(program_id, accounts)?;
+        SwapFunctions::sender_refund(
+            program_id,
+            accounts,
+            SenderRefundParams {
+                secret_hash: terms.secret_hash,
+                lock_time,
+                amount: terms.amount,
+                receiver: terms.receiver,
+                token_program: terms.token_program,
+                vault_bump_seed: terms.vault_bump_seed,
+                vault_bump_seed_data: terms.vault_bump_seed_data,
+            },
+        )
+    }
+    /// Read-only, so it can be simulated or invoked by other programs to learn a payment's status.
+    pub fn query_payment(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        params: QueryPaymentParams,
+    ) -> ProgramResult {
+        let vault_pda_data = next_account_info(&mut accounts.iter())?;
+        if vault_pda_data.owner != program_id {
+            return Err(SwapError::InvalidOwner.into());
+        }
+        let payment = Payment::unpack(
+            &vault_pda_data
+                .try_borrow_data()
+                .map_err(|_| SwapError::SwapAccountNotFound)?,
+        )?;
+        let mint = (params.token_program != Pubkey::default()).then_some(&params.mint);
+        let payment_hash = SwapFunctions::payment_hash(
+            &params.receiver,
+            &params.sender,
+            &params.secret_hash,
+            payment.secret_hash_algorithm,
+            &params.token_program,
+            params.amount,
+            mint,
+            payment.relayer_fee,
+            payment.watcher_reward,
+        );
+        if payment.payment_hash != payment_hash.to_bytes() {
+            return Err(SwapError::InvalidPaymentHash.into());
+        }
+        // The payment hash does not commit to the lock time, the stored one is authoritative.
+        if payment.lock_time != params.lock_time {
+            return Err(SwapError::InvalidLockTime.into());
+        }
+        set_return_data(&PaymentStatus::new(&payment, &Clock::get()?).pack());
+        Ok(())
+    }
+    pub fn close_payment(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        params: ClosePaymentParams,
+    ) -> ProgramResult {
+        let accounts_iter = &mut accounts.iter();
+        let sender_account = next_account_info(accounts_iter)?;
+        let vault_pda_data = next_account_info(accounts_iter)?;
+        let vault_pda = next_account_info(accounts_iter)?;
+        let token_accounts = if params.token_program == Pubkey::new_from_array([0; 32]) {
+            None
+        } else {
+            Some(TokenAccounts::next(accounts_iter)?)
+        };
+
+        SwapFunctions::validate_accounts(sender_account, vault_pda_data, vault_pda)?;
+        let decimals = match &token_accounts {
+            Some(token_accounts) => {
+                token_accounts.validate(&params.token_program, sender_account.key, vault_pda.key)?
+            }
+            None => 0,
+        };
+
+        let payment_seeds = PaymentSeeds::new(
+            params.lock_time,
+            &params.secret_hash,
+            sender_account.key,
+            &params.receiver,
+            token_accounts.as_ref().map(|accounts| accounts.mint.key),
+        );
+        let scheme =
+            payment_seeds.scheme(program_id, vault_pda_data.key, params.vault_bump_seed_data);
+        payment_seeds.verify_vault_addresses(
+            program_id,
+            scheme,
+            vault_pda.key,
+            params.vault_bump_seed,
+            vault_pda_data.key,
+            params.vault_bump_seed_data,
+        )?;
+        let vault_bump = [params.vault_bump_seed];
+        let vault_seeds = payment_seeds.seeds(VAULT_SEED, scheme, &vault_bump);
+
+        if vault_pda_data.owner != program_id {
+            return Err(SwapError::InvalidOwner.into());
+        }
+
+        let swap_payment = Payment::unpack(
+            &vault_pda_data
+                .try_borrow_data()
+                .map_err(|_| SwapError::SwapAccountNotFound)?,
+        )?;
+        let payment_hash = SwapFunctions::payment_hash(
+            &params.receiver,
+            sender_account.key,
+            &params.secret_hash,
+            swap_payment.secret_hash_algorithm,
+            &params.token_program,
+            params.amount,
+            token_accounts.as_ref().map(|accounts| accounts.mint.key),
+            swap_payment.relayer_fee,
+            swap_payment.watcher_reward,
+        );
+        if swap_payment.payment_hash != payment_hash.to_bytes() {
+            return Err(SwapError::InvalidPaymentHash.into());
+        }
+        if swap_payment.state != PaymentState::ReceiverSpent
+            && swap_payment.state != PaymentState::SenderRefunded
+        {
+            return Err(SwapError::InvalidPaymentState.into());
+        }
+
+        if let Some(token_accounts) = &token_accounts {
+            SwapFunctions::close_vault_token_account(
+                token_accounts,
+                vault_pda,
+                sender_account,
+                decimals,
+                &vault_seeds,
+            )?;
+        }
+
+        let vault_lamports = vault_pda.lamports();
+        if vault_lamports > 0 {
             SwapFunctions::transfer(
                 vault_pda,
                 sender_account,
                 &[vault_pda.clone(), sender_account.clone()],
-                params.amount,
-                vault_seeds,
-            )
-        } else {
-            Err(ProgramError::Custom(NOT_SUPPORTED))
+                vault_lamports,
+                &vault_seeds,
+            )?;
         }
+
+        SwapFunctions::close_data_account(vault_pda_data, sender_account)
     }
 }
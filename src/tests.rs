@@ -1,13 +1,29 @@
-use crate::instruction::AtomicSwapInstruction;
+use crate::config::{Config, ConfigSettings};
+use crate::error::SwapError;
+use crate::event::{self, SwapEvent, SwapEventKind};
+use crate::instruction::{self, AtomicSwapInstruction, SplTokenAccounts};
+use crate::lock_time::{self, LockTimeKind};
+use crate::payment::{Payment, PaymentState, PaymentStatus, PaymentTerms, SecretHashAlgorithm};
+use crate::pda::{find_config_address, PaymentSeeds, SeedScheme, VAULT_DATA_SEED, VAULT_SEED};
 use crate::satomic_swap::process_instruction;
+use solana_program::clock::Clock;
+use solana_program::epoch_schedule::EpochSchedule;
 use solana_program::hash::Hasher;
-use solana_program_test::{processor, tokio, ProgramTest, ProgramTestContext};
+use solana_program_test::{processor, tokio, BanksClientError, ProgramTest, ProgramTestContext};
 use solana_sdk::{
-    instruction::{AccountMeta, Instruction},
+    account::AccountSharedData,
+    bpf_loader_upgradeable::{self, UpgradeableLoaderState},
+    instruction::{AccountMeta, Instruction, InstructionError},
+    program_error::ProgramError,
     pubkey::Pubkey,
     signature::{Keypair, Signer},
-    transaction::Transaction,
+    system_instruction,
+    transaction::{Transaction, TransactionError},
 };
+use spl_token_2022::extension::transfer_fee::instruction as transfer_fee_instruction;
+use spl_token_2022::extension::{ExtensionType, StateWithExtensions};
+use spl_token_2022::state::{Account as TokenAccount, Mint};
+use std::time::Duration;
 
 pub struct InitializeValues {
     program_id: Pubkey,
@@ -16,10 +32,16 @@ pub struct InitializeValues {
     sender_account: Keypair,
     receiver_account: Keypair,
     rent_exemption_lamports: u64,
-    secret: [u8; 32],
+    vault_rent_exemption_lamports: u64,
+    secret: Vec<u8>,
     secret_hash: [u8; 32],
+    secret_hash_algorithm: SecretHashAlgorithm,
     lock_time: u64,
+    lock_time_kind: LockTimeKind,
+    spend_deadline: Option<u64>,
     amount: u64,
+    relayer_fee: u64,
+    watcher_reward: u64,
     token_program: Pubkey,
     receiver: Pubkey,
     sender: Pubkey,
@@ -30,6 +52,18 @@ pub struct InitializeValues {
     fee: u64,
 }
 
+fn find_vault_addresses(
+    program_id: &Pubkey,
+    lock_time: u64,
+    secret_hash: &[u8; 32],
+    sender: &Pubkey,
+    receiver: &Pubkey,
+    mint: Option<&Pubkey>,
+) -> ((Pubkey, u8), (Pubkey, u8)) {
+    PaymentSeeds::new(lock_time, secret_hash, sender, receiver, mint)
+        .find_vault_addresses(program_id)
+}
+
 async fn initialize() -> Result<InitializeValues, Box<dyn std::error::Error>> {
     let program_id = Pubkey::new_unique();
     let system_program = solana_program::system_program::id();
@@ -105,25 +139,30 @@ async fn initialize() -> Result<InitializeValues, Box<dyn std::error::Error>> {
     assert_eq!(recipient_balance, lamports_initial_balance);
 
     let rent = context.banks_client.get_rent().await.expect("get rent");
-    let rent_exemption_lamports = rent.minimum_balance(41);
+    let rent_exemption_lamports = rent.minimum_balance(Payment::LEN);
+    let vault_rent_exemption_lamports = rent.minimum_balance(0);
 
-    let secret = [0u8; 32];
+    let secret = vec![0u8; 32];
     let mut hasher = Hasher::default();
     hasher.hash(&secret);
     let secret_hash = hasher.result();
     let secret_hash = secret_hash.to_bytes();
-    let lock_time: u64 = 1;
+    let clock = context.banks_client.get_sysvar::<Clock>().await?;
+    let lock_time = clock.unix_timestamp as u64 + 1000;
     let amount: u64 = 10000;
     let token_program = Pubkey::new_from_array([0; 32]);
     let receiver = receiver_account.pubkey();
     let sender = sender_account.pubkey();
 
-    let vault_seeds: &[&[u8]] = &[b"swap", &lock_time.to_le_bytes()[..], &secret_hash[..]];
-    let vault_seeds_data: &[&[u8]] =
-        &[b"swap_data", &lock_time.to_le_bytes()[..], &secret_hash[..]];
-    let (vault_pda, vault_bump_seed) = Pubkey::find_program_address(vault_seeds, &program_id);
-    let (vault_pda_data, vault_bump_seed_data) =
-        Pubkey::find_program_address(vault_seeds_data, &program_id);
+    let ((vault_pda, vault_bump_seed), (vault_pda_data, vault_bump_seed_data)) =
+        find_vault_addresses(
+            &program_id,
+            lock_time,
+            &secret_hash,
+            &sender,
+            &receiver,
+            None,
+        );
 
     Ok(InitializeValues {
         program_id,
@@ -132,10 +171,16 @@ async fn initialize() -> Result<InitializeValues, Box<dyn std::error::Error>> {
         sender_account,
         receiver_account,
         rent_exemption_lamports,
+        vault_rent_exemption_lamports,
         secret,
         secret_hash,
+        secret_hash_algorithm: SecretHashAlgorithm::Sha256,
         lock_time,
+        lock_time_kind: LockTimeKind::UnixTimestamp,
+        spend_deadline: None,
         amount,
+        relayer_fee: 0,
+        watcher_reward: 0,
         token_program,
         receiver,
         sender,
@@ -147,6 +192,31 @@ async fn initialize() -> Result<InitializeValues, Box<dyn std::error::Error>> {
     })
 }
 
+/// Moves the bank clock to `unix_timestamp` without producing a new block.
+async fn set_unix_timestamp(
+    context: &mut ProgramTestContext,
+    unix_timestamp: u64,
+) -> Result<(), BanksClientError> {
+    let mut clock = context.banks_client.get_sysvar::<Clock>().await?;
+    clock.unix_timestamp = unix_timestamp as i64;
+    context.set_sysvar(&clock);
+    Ok(())
+}
+
+fn new_payment(values: &InitializeValues) -> instruction::NewPayment {
+    instruction::NewPayment {
+        receiver: values.receiver,
+        secret_hash: values.secret_hash,
+        secret_hash_algorithm: values.secret_hash_algorithm,
+        lock_time: values.lock_time,
+        lock_time_kind: values.lock_time_kind,
+        spend_deadline: values.spend_deadline,
+        amount: values.amount,
+        relayer_fee: values.relayer_fee,
+        watcher_reward: values.watcher_reward,
+    }
+}
+
 async fn submit_payment() -> Result<InitializeValues, Box<dyn std::error::Error>> {
     let mut values = initialize().await?;
     let sender_account_balance = values
@@ -167,26 +237,8 @@ async fn submit_payment() -> Result<InitializeValues, Box<dyn std::error::Error>
         "before submit_payment: vault_pda balance: {}",
         vault_pda_balance
     );
-    let swap_instruction = AtomicSwapInstruction::LamportsPayment {
-        secret_hash: values.secret_hash,
-        lock_time: values.lock_time,
-        amount: values.amount,
-        receiver: values.receiver,
-        rent_exemption_lamports: values.rent_exemption_lamports,
-        vault_bump_seed: values.vault_bump_seed,
-        vault_bump_seed_data: values.vault_bump_seed_data,
-    };
-    let data = swap_instruction.pack();
-    let instruction = Instruction {
-        program_id: values.program_id,
-        accounts: vec![
-            AccountMeta::new(values.sender_account.pubkey(), true),
-            AccountMeta::new(values.vault_pda_data, false),
-            AccountMeta::new(values.vault_pda, false),
-            AccountMeta::new(values.system_program, false),
-        ],
-        data,
-    };
+    let instruction =
+        instruction::lamports_payment(&values.program_id, &values.sender, &new_payment(&values));
 
     let mut transaction =
         Transaction::new_with_payer(&[instruction], Some(&values.sender_account.pubkey()));
@@ -219,11 +271,15 @@ async fn submit_payment() -> Result<InitializeValues, Box<dyn std::error::Error>
     );
     assert_eq!(
         sender_account_balance_after,
-        sender_account_balance - (values.fee + values.amount + values.rent_exemption_lamports * 2)
+        sender_account_balance
+            - (values.fee
+                + values.amount
+                + values.rent_exemption_lamports
+                + values.vault_rent_exemption_lamports)
     );
     assert_eq!(
         vault_pda_balance_after,
-        vault_pda_balance + values.amount + values.rent_exemption_lamports
+        vault_pda_balance + values.amount + values.vault_rent_exemption_lamports
     );
     Ok(values)
 }
@@ -255,28 +311,18 @@ async fn test_receiver_spend() -> Result<(), Box<dyn std::error::Error>> {
         "before submit_payment: vault_pda balance: {}",
         vault_pda_balance
     );
-    let swap_instruction = AtomicSwapInstruction::ReceiverSpend {
-        secret: values.secret,
-        lock_time: values.lock_time,
-        amount: values.amount,
-        sender: values.sender,
-        token_program: values.token_program,
-        vault_bump_seed: values.vault_bump_seed,
-        vault_bump_seed_data: values.vault_bump_seed_data,
-    };
-    let data = swap_instruction.pack();
-
     values.context.last_blockhash = values.context.banks_client.get_latest_blockhash().await?;
-    let instruction = Instruction {
-        program_id: values.program_id,
-        accounts: vec![
-            AccountMeta::new(values.receiver_account.pubkey(), true),
-            AccountMeta::new(values.vault_pda_data, false),
-            AccountMeta::new(values.vault_pda, false),
-            AccountMeta::new(values.system_program, false),
-        ],
-        data,
-    };
+    let instruction = instruction::receiver_spend(
+        &values.program_id,
+        &values.receiver,
+        &values.sender,
+        &values.secret,
+        values.secret_hash_algorithm,
+        values.lock_time,
+        values.amount,
+        None,
+    )
+    .expect("valid secret");
 
     let mut transaction =
         Transaction::new_with_payer(&[instruction], Some(&values.receiver_account.pubkey()));
@@ -336,28 +382,9 @@ async fn test_sender_refund() -> Result<(), Box<dyn std::error::Error>> {
         "before submit_payment: vault_pda balance: {}",
         vault_pda_balance
     );
-    let swap_instruction = AtomicSwapInstruction::SenderRefund {
-        secret_hash: values.secret_hash,
-        lock_time: values.lock_time,
-        amount: values.amount,
-        receiver: values.receiver,
-        token_program: values.token_program,
-        vault_bump_seed: values.vault_bump_seed,
-        vault_bump_seed_data: values.vault_bump_seed_data,
-    };
-    let data = swap_instruction.pack();
-
     values.context.last_blockhash = values.context.banks_client.get_latest_blockhash().await?;
-    let instruction = Instruction {
-        program_id: values.program_id,
-        accounts: vec![
-            AccountMeta::new(values.sender_account.pubkey(), true),
-            AccountMeta::new(values.vault_pda_data, false),
-            AccountMeta::new(values.vault_pda, false),
-            AccountMeta::new(values.system_program, false),
-        ],
-        data,
-    };
+    set_unix_timestamp(&mut values.context, values.lock_time + 1).await?;
+    let instruction = sender_refund_instruction(&values);
 
     let mut transaction =
         Transaction::new_with_payer(&[instruction], Some(&values.sender_account.pubkey()));
@@ -395,3 +422,2977 @@ async fn test_sender_refund() -> Result<(), Box<dyn std::error::Error>> {
 
     Ok(())
 }
+
+pub struct SPLTokenValues {
+    values: InitializeValues,
+    mint: Pubkey,
+    mint_extensions: Vec<ExtensionType>,
+    sender_token_account: Pubkey,
+    vault_token_account: Pubkey,
+    receiver_token_account: Pubkey,
+    escrowed_amount: u64,
+}
+
+const TRANSFER_FEE_BASIS_POINTS: u16 = 100;
+
+async fn process_instructions(
+    context: &mut ProgramTestContext,
+    instructions: &[Instruction],
+    signers: &[&Keypair],
+) -> Result<(), BanksClientError> {
+    context.last_blockhash = context.banks_client.get_latest_blockhash().await?;
+    let mut all_signers = vec![&context.payer];
+    all_signers.extend_from_slice(signers);
+    let mut transaction = Transaction::new_with_payer(instructions, Some(&context.payer.pubkey()));
+    transaction.sign(&all_signers, context.last_blockhash);
+    context.banks_client.process_transaction(transaction).await
+}
+
+/// Makes the payer the program's upgrade authority and initializes the config with it as the
+/// admin.
+async fn initialize_config(
+    values: &mut InitializeValues,
+    settings: &ConfigSettings,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let payer = values.context.payer.pubkey();
+    set_upgrade_authority(values, &payer).await?;
+    let initialize =
+        instruction::initialize_config(&values.program_id, &payer, &payer, settings.clone());
+    process_instructions(&mut values.context, &[initialize], &[]).await?;
+    Ok(())
+}
+
+/// Builtin programs have no program data account, so the one of an upgradeable program is set up.
+async fn set_upgrade_authority(
+    values: &mut InitializeValues,
+    upgrade_authority: &Pubkey,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let (program_data, _) =
+        Pubkey::find_program_address(&[values.program_id.as_ref()], &bpf_loader_upgradeable::id());
+    let data = bincode::serialize(&UpgradeableLoaderState::ProgramData {
+        slot: 0,
+        upgrade_authority_address: Some(*upgrade_authority),
+    })?;
+    let rent = values.context.banks_client.get_rent().await?;
+    let mut account = AccountSharedData::new(
+        rent.minimum_balance(data.len()),
+        data.len(),
+        &bpf_loader_upgradeable::id(),
+    );
+    account.set_data_from_slice(&data);
+    values.context.set_account(&program_data, &account);
+    Ok(())
+}
+
+async fn stored_config(
+    values: &mut InitializeValues,
+) -> Result<Config, Box<dyn std::error::Error>> {
+    let account = values
+        .context
+        .banks_client
+        .get_account(find_config_address(&values.program_id).0)
+        .await?
+        .expect("account not found");
+    Ok(Config::unpack(&account.data)?)
+}
+
+fn assert_custom_error(result: Result<(), BanksClientError>, expected: SwapError) {
+    match result.map_err(|e| e.unwrap()) {
+        Err(error) => assert_eq!(SwapError::from_transaction_error(&error), Some(expected)),
+        other => panic!("expected {:?}, got {:?}", expected, other),
+    }
+}
+
+async fn create_token_account(
+    context: &mut ProgramTestContext,
+    token_program: &Pubkey,
+    mint: &Pubkey,
+    mint_extensions: &[ExtensionType],
+    owner: &Pubkey,
+) -> Result<Pubkey, Box<dyn std::error::Error>> {
+    let token_account = Keypair::new();
+    let rent = context.banks_client.get_rent().await?;
+    let mut account_extensions =
+        ExtensionType::get_required_init_account_extensions(mint_extensions);
+    if *token_program == spl_token_2022::id() {
+        account_extensions.push(ExtensionType::ImmutableOwner);
+    }
+    let space = ExtensionType::try_calculate_account_len::<TokenAccount>(&account_extensions)?;
+    let mut instructions = vec![system_instruction::create_account(
+        &context.payer.pubkey(),
+        &token_account.pubkey(),
+        rent.minimum_balance(space),
+        space as u64,
+        token_program,
+    )];
+    if *token_program == spl_token_2022::id() {
+        instructions.push(spl_token_2022::instruction::initialize_immutable_owner(
+            token_program,
+            &token_account.pubkey(),
+        )?);
+    }
+    instructions.push(spl_token_2022::instruction::initialize_account3(
+        token_program,
+        &token_account.pubkey(),
+        mint,
+        owner,
+    )?);
+    process_instructions(context, &instructions, &[&token_account]).await?;
+    Ok(token_account.pubkey())
+}
+
+async fn get_token_balance(
+    context: &mut ProgramTestContext,
+    token_account: &Pubkey,
+) -> Result<u64, Box<dyn std::error::Error>> {
+    let account = context
+        .banks_client
+        .get_account(*token_account)
+        .await?
+        .expect("token account not found");
+    Ok(StateWithExtensions::<TokenAccount>::unpack(&account.data)?
+        .base
+        .amount)
+}
+
+fn mint_extension_instruction(
+    token_program: &Pubkey,
+    mint: &Pubkey,
+    authority: &Pubkey,
+    extension: ExtensionType,
+) -> Result<Instruction, Box<dyn std::error::Error>> {
+    let instruction = match extension {
+        ExtensionType::TransferFeeConfig => {
+            transfer_fee_instruction::initialize_transfer_fee_config(
+                token_program,
+                mint,
+                Some(authority),
+                Some(authority),
+                TRANSFER_FEE_BASIS_POINTS,
+                u64::MAX,
+            )?
+        }
+        ExtensionType::NonTransferable => {
+            spl_token_2022::instruction::initialize_non_transferable_mint(token_program, mint)?
+        }
+        ExtensionType::PermanentDelegate => {
+            spl_token_2022::instruction::initialize_permanent_delegate(
+                token_program,
+                mint,
+                authority,
+            )?
+        }
+        _ => unimplemented!("unsupported mint extension in tests: {:?}", extension),
+    };
+    Ok(instruction)
+}
+
+async fn initialize_spl_token(
+    token_program: Pubkey,
+    mint_extensions: &[ExtensionType],
+) -> Result<SPLTokenValues, Box<dyn std::error::Error>> {
+    let mut values = initialize().await?;
+    values.token_program = token_program;
+
+    let mint = Keypair::new();
+    let mint_authority = values.context.payer.pubkey();
+    let rent = values.context.banks_client.get_rent().await?;
+    let space = ExtensionType::try_calculate_account_len::<Mint>(mint_extensions)?;
+    let mut instructions = vec![system_instruction::create_account(
+        &values.context.payer.pubkey(),
+        &mint.pubkey(),
+        rent.minimum_balance(space),
+        space as u64,
+        &token_program,
+    )];
+    for extension in mint_extensions {
+        instructions.push(mint_extension_instruction(
+            &token_program,
+            &mint.pubkey(),
+            &mint_authority,
+            *extension,
+        )?);
+    }
+    instructions.push(spl_token_2022::instruction::initialize_mint2(
+        &token_program,
+        &mint.pubkey(),
+        &mint_authority,
+        None,
+        6,
+    )?);
+    process_instructions(&mut values.context, &instructions, &[&mint]).await?;
+    let mint = mint.pubkey();
+    (
+        (values.vault_pda, values.vault_bump_seed),
+        (values.vault_pda_data, values.vault_bump_seed_data),
+    ) = find_vault_addresses(
+        &values.program_id,
+        values.lock_time,
+        &values.secret_hash,
+        &values.sender,
+        &values.receiver,
+        Some(&mint),
+    );
+
+    let sender_token_account = create_token_account(
+        &mut values.context,
+        &token_program,
+        &mint,
+        mint_extensions,
+        &values.sender,
+    )
+    .await?;
+    let vault_token_account = create_token_account(
+        &mut values.context,
+        &token_program,
+        &mint,
+        mint_extensions,
+        &values.vault_pda,
+    )
+    .await?;
+    let receiver_token_account = create_token_account(
+        &mut values.context,
+        &token_program,
+        &mint,
+        mint_extensions,
+        &values.receiver,
+    )
+    .await?;
+
+    let mint_to = spl_token_2022::instruction::mint_to(
+        &token_program,
+        &mint,
+        &sender_token_account,
+        &mint_authority,
+        &[],
+        values.amount,
+    )?;
+    process_instructions(&mut values.context, &[mint_to], &[]).await?;
+
+    Ok(SPLTokenValues {
+        values,
+        mint,
+        mint_extensions: mint_extensions.to_vec(),
+        sender_token_account,
+        vault_token_account,
+        receiver_token_account,
+        escrowed_amount: 0,
+    })
+}
+
+impl SPLTokenValues {
+    fn token_accounts(&self, user_token_account: Pubkey) -> SplTokenAccounts {
+        SplTokenAccounts {
+            mint: self.mint,
+            token_program: self.values.token_program,
+            user_token_account,
+            vault_token_account: self.vault_token_account,
+        }
+    }
+}
+
+fn spl_token_payment_instruction(spl_values: &SPLTokenValues) -> Instruction {
+    let values = &spl_values.values;
+    instruction::spl_token_payment(
+        &values.program_id,
+        &values.sender,
+        &new_payment(values),
+        &spl_values.token_accounts(spl_values.sender_token_account),
+    )
+}
+
+async fn submit_spl_token_payment_with(
+    token_program: Pubkey,
+    mint_extensions: &[ExtensionType],
+) -> Result<SPLTokenValues, Box<dyn std::error::Error>> {
+    let mut spl_values = initialize_spl_token(token_program, mint_extensions).await?;
+    let instruction = spl_token_payment_instruction(&spl_values);
+    let values = &mut spl_values.values;
+    let sender_account = values.sender_account.insecure_clone();
+    process_instructions(&mut values.context, &[instruction], &[&sender_account]).await?;
+
+    let transfer_fee = if mint_extensions.contains(&ExtensionType::TransferFeeConfig) {
+        values.amount * TRANSFER_FEE_BASIS_POINTS as u64 / 10_000
+    } else {
+        0
+    };
+    spl_values.escrowed_amount = values.amount - transfer_fee;
+    assert_eq!(
+        get_token_balance(&mut values.context, &spl_values.sender_token_account).await?,
+        0
+    );
+    assert_eq!(
+        get_token_balance(&mut values.context, &spl_values.vault_token_account).await?,
+        spl_values.escrowed_amount
+    );
+    Ok(spl_values)
+}
+
+async fn submit_spl_token_payment() -> Result<SPLTokenValues, Box<dyn std::error::Error>> {
+    submit_spl_token_payment_with(spl_token::id(), &[]).await
+}
+
+#[tokio::test]
+async fn test_spl_token_payment() -> Result<(), Box<dyn std::error::Error>> {
+    let _ = submit_spl_token_payment().await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_spl_token_payment_wrong_vault_owner() -> Result<(), Box<dyn std::error::Error>> {
+    let mut spl_values = initialize_spl_token(spl_token::id(), &[]).await?;
+    let values = &mut spl_values.values;
+    let receiver = values.receiver;
+    let foreign_token_account = create_token_account(
+        &mut values.context,
+        &values.token_program,
+        &spl_values.mint,
+        &spl_values.mint_extensions,
+        &receiver,
+    )
+    .await?;
+
+    let mut token_accounts = spl_values.token_accounts(spl_values.sender_token_account);
+    token_accounts.vault_token_account = foreign_token_account;
+    let values = &mut spl_values.values;
+    let instruction = instruction::spl_token_payment(
+        &values.program_id,
+        &values.sender,
+        &new_payment(values),
+        &token_accounts,
+    );
+    let sender_account = values.sender_account.insecure_clone();
+    let result =
+        process_instructions(&mut values.context, &[instruction], &[&sender_account]).await;
+    assert_custom_error(result, SwapError::InvalidTokenAccountOwner);
+    assert_eq!(
+        get_token_balance(&mut values.context, &spl_values.sender_token_account).await?,
+        values.amount
+    );
+    Ok(())
+}
+
+async fn spl_receiver_spend(spl_values: &mut SPLTokenValues) -> Result<(), BanksClientError> {
+    let token_accounts = spl_values.token_accounts(spl_values.receiver_token_account);
+    let values = &mut spl_values.values;
+    let instruction = instruction::receiver_spend(
+        &values.program_id,
+        &values.receiver,
+        &values.sender,
+        &values.secret,
+        values.secret_hash_algorithm,
+        values.lock_time,
+        spl_values.escrowed_amount,
+        Some(&token_accounts),
+    )
+    .expect("valid secret");
+    let receiver_account = values.receiver_account.insecure_clone();
+    process_instructions(&mut values.context, &[instruction], &[&receiver_account]).await
+}
+
+async fn spl_sender_refund(spl_values: &mut SPLTokenValues) -> Result<(), BanksClientError> {
+    let token_accounts = spl_values.token_accounts(spl_values.sender_token_account);
+    let values = &mut spl_values.values;
+    set_unix_timestamp(&mut values.context, values.lock_time + 1).await?;
+    let instruction = instruction::sender_refund(
+        &values.program_id,
+        &values.sender,
+        &values.receiver,
+        values.secret_hash,
+        values.lock_time,
+        spl_values.escrowed_amount,
+        Some(&token_accounts),
+    );
+    let sender_account = values.sender_account.insecure_clone();
+    process_instructions(&mut values.context, &[instruction], &[&sender_account]).await
+}
+
+#[tokio::test]
+async fn test_spl_receiver_spend() -> Result<(), Box<dyn std::error::Error>> {
+    let mut spl_values = submit_spl_token_payment().await?;
+    spl_receiver_spend(&mut spl_values).await?;
+
+    let context = &mut spl_values.values.context;
+    assert_eq!(
+        get_token_balance(context, &spl_values.vault_token_account).await?,
+        0
+    );
+    assert_eq!(
+        get_token_balance(context, &spl_values.receiver_token_account).await?,
+        spl_values.values.amount
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_spl_sender_refund() -> Result<(), Box<dyn std::error::Error>> {
+    let mut spl_values = submit_spl_token_payment().await?;
+    spl_sender_refund(&mut spl_values).await?;
+
+    let context = &mut spl_values.values.context;
+    assert_eq!(
+        get_token_balance(context, &spl_values.vault_token_account).await?,
+        0
+    );
+    assert_eq!(
+        get_token_balance(context, &spl_values.sender_token_account).await?,
+        spl_values.values.amount
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_token_2022_transfer_fee_receiver_spend() -> Result<(), Box<dyn std::error::Error>> {
+    let mut spl_values =
+        submit_spl_token_payment_with(spl_token_2022::id(), &[ExtensionType::TransferFeeConfig])
+            .await?;
+    assert!(spl_values.escrowed_amount < spl_values.values.amount);
+    spl_receiver_spend(&mut spl_values).await?;
+
+    let escrowed_amount = spl_values.escrowed_amount;
+    let context = &mut spl_values.values.context;
+    assert_eq!(
+        get_token_balance(context, &spl_values.vault_token_account).await?,
+        0
+    );
+    assert_eq!(
+        get_token_balance(context, &spl_values.receiver_token_account).await?,
+        escrowed_amount - escrowed_amount * TRANSFER_FEE_BASIS_POINTS as u64 / 10_000
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_token_2022_transfer_fee_wrong_amount() -> Result<(), Box<dyn std::error::Error>> {
+    let mut spl_values =
+        submit_spl_token_payment_with(spl_token_2022::id(), &[ExtensionType::TransferFeeConfig])
+            .await?;
+    spl_values.escrowed_amount = spl_values.values.amount;
+    let result = spl_sender_refund(&mut spl_values).await;
+    assert_custom_error(result, SwapError::InvalidPaymentHash);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_token_2022_unsupported_extensions() -> Result<(), Box<dyn std::error::Error>> {
+    for (extension, error) in [
+        (
+            ExtensionType::NonTransferable,
+            SwapError::NonTransferableMint,
+        ),
+        (
+            ExtensionType::PermanentDelegate,
+            SwapError::PermanentDelegateNotSupported,
+        ),
+    ] {
+        let mut spl_values = initialize_spl_token(spl_token_2022::id(), &[extension]).await?;
+        let instruction = spl_token_payment_instruction(&spl_values);
+        let values = &mut spl_values.values;
+        let sender_account = values.sender_account.insecure_clone();
+        let result =
+            process_instructions(&mut values.context, &[instruction], &[&sender_account]).await;
+        assert_custom_error(result, error);
+    }
+    Ok(())
+}
+
+fn sender_refund_instruction(values: &InitializeValues) -> Instruction {
+    instruction::sender_refund(
+        &values.program_id,
+        &values.sender,
+        &values.receiver,
+        values.secret_hash,
+        values.lock_time,
+        values.amount,
+        None,
+    )
+}
+
+fn close_payment_instruction(
+    values: &InitializeValues,
+    amount: u64,
+    token_accounts: Option<&SplTokenAccounts>,
+) -> Instruction {
+    instruction::close_payment(
+        &values.program_id,
+        &values.sender,
+        &values.receiver,
+        values.secret_hash,
+        values.lock_time,
+        amount,
+        token_accounts,
+    )
+}
+
+#[tokio::test]
+async fn test_close_payment() -> Result<(), Box<dyn std::error::Error>> {
+    let mut values = submit_payment().await?;
+    let sender_account = values.sender_account.insecure_clone();
+    set_unix_timestamp(&mut values.context, values.lock_time + 1).await?;
+    let refund = sender_refund_instruction(&values);
+    process_instructions(&mut values.context, &[refund], &[&sender_account]).await?;
+
+    let sender_account_balance = values
+        .context
+        .banks_client
+        .get_balance(sender_account.pubkey())
+        .await?;
+    let close = close_payment_instruction(&values, values.amount, None);
+    process_instructions(&mut values.context, &[close], &[&sender_account]).await?;
+
+    let sender_account_balance_after = values
+        .context
+        .banks_client
+        .get_balance(sender_account.pubkey())
+        .await?;
+    assert_eq!(
+        sender_account_balance_after,
+        sender_account_balance
+            + values.rent_exemption_lamports
+            + values.vault_rent_exemption_lamports
+    );
+    for closed in [values.vault_pda_data, values.vault_pda] {
+        assert!(values
+            .context
+            .banks_client
+            .get_account(closed)
+            .await?
+            .is_none());
+    }
+
+    let refund = sender_refund_instruction(&values);
+    let result = process_instructions(&mut values.context, &[refund], &[&sender_account]).await;
+    assert_custom_error(result, SwapError::InvalidOwner);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_close_payment_not_completed() -> Result<(), Box<dyn std::error::Error>> {
+    let mut values = submit_payment().await?;
+    let sender_account = values.sender_account.insecure_clone();
+    let close = close_payment_instruction(&values, values.amount, None);
+    let result = process_instructions(&mut values.context, &[close], &[&sender_account]).await;
+    assert_custom_error(result, SwapError::InvalidPaymentState);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_close_payment_token_2022_transfer_fee() -> Result<(), Box<dyn std::error::Error>> {
+    let mut spl_values =
+        submit_spl_token_payment_with(spl_token_2022::id(), &[ExtensionType::TransferFeeConfig])
+            .await?;
+    spl_receiver_spend(&mut spl_values).await?;
+
+    let token_accounts = spl_values.token_accounts(spl_values.sender_token_account);
+    let close = close_payment_instruction(
+        &spl_values.values,
+        spl_values.escrowed_amount,
+        Some(&token_accounts),
+    );
+    let values = &mut spl_values.values;
+    let sender_account = values.sender_account.insecure_clone();
+    process_instructions(&mut values.context, &[close], &[&sender_account]).await?;
+
+    for closed in [values.vault_pda_data, spl_values.vault_token_account] {
+        assert!(values
+            .context
+            .banks_client
+            .get_account(closed)
+            .await?
+            .is_none());
+    }
+    Ok(())
+}
+
+fn lamports_payment_instruction(values: &InitializeValues, sender: &Pubkey) -> Instruction {
+    let swap_instruction = AtomicSwapInstruction::LamportsPayment {
+        secret_hash: values.secret_hash,
+        secret_hash_algorithm: values.secret_hash_algorithm,
+        lock_time: values.lock_time,
+        lock_time_kind: values.lock_time_kind,
+        spend_deadline: values.spend_deadline.unwrap_or(values.lock_time),
+        amount: values.amount,
+        relayer_fee: values.relayer_fee,
+        watcher_reward: values.watcher_reward,
+        receiver: values.receiver,
+        vault_bump_seed: values.vault_bump_seed,
+        vault_bump_seed_data: values.vault_bump_seed_data,
+    };
+    Instruction {
+        program_id: values.program_id,
+        accounts: vec![
+            AccountMeta::new(*sender, true),
+            AccountMeta::new(values.vault_pda_data, false),
+            AccountMeta::new(values.vault_pda, false),
+            AccountMeta::new(values.system_program, false),
+            AccountMeta::new_readonly(find_config_address(&values.program_id).0, false),
+        ],
+        data: swap_instruction.pack(),
+    }
+}
+
+#[tokio::test]
+async fn test_payment_same_secret_hash_other_sender() -> Result<(), Box<dyn std::error::Error>> {
+    let mut values = initialize().await?;
+    let squatter = values.context.payer.insecure_clone();
+    let ((squatter_vault_pda, _), (squatter_vault_pda_data, _)) = find_vault_addresses(
+        &values.program_id,
+        values.lock_time,
+        &values.secret_hash,
+        &squatter.pubkey(),
+        &values.receiver,
+        None,
+    );
+    assert_ne!(squatter_vault_pda_data, values.vault_pda_data);
+
+    // Reusing the victim's addresses under another signer is rejected before any account is touched.
+    let squat = lamports_payment_instruction(&values, &squatter.pubkey());
+    let result = process_instructions(&mut values.context, &[squat], &[]).await;
+    assert_custom_error(result, SwapError::VaultAddressMismatch);
+
+    let squatter_payment = instruction::lamports_payment(
+        &values.program_id,
+        &squatter.pubkey(),
+        &new_payment(&values),
+    );
+    assert_eq!(squatter_payment.accounts[1].pubkey, squatter_vault_pda_data);
+    assert_eq!(squatter_payment.accounts[2].pubkey, squatter_vault_pda);
+    process_instructions(&mut values.context, &[squatter_payment], &[]).await?;
+
+    let sender_account = values.sender_account.insecure_clone();
+    let payment = lamports_payment_instruction(&values, &sender_account.pubkey());
+    process_instructions(&mut values.context, &[payment], &[&sender_account]).await?;
+    assert_eq!(
+        values
+            .context
+            .banks_client
+            .get_balance(values.vault_pda)
+            .await?,
+        values.amount + values.vault_rent_exemption_lamports
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_payment_prefunded_data_account() -> Result<(), Box<dyn std::error::Error>> {
+    let mut values = initialize().await?;
+    let rent = values.context.banks_client.get_rent().await?;
+    let prefund = system_instruction::transfer(
+        &values.context.payer.pubkey(),
+        &values.vault_pda_data,
+        rent.minimum_balance(0),
+    );
+    process_instructions(&mut values.context, &[prefund], &[]).await?;
+
+    let sender_account = values.sender_account.insecure_clone();
+    let payment = lamports_payment_instruction(&values, &sender_account.pubkey());
+    process_instructions(&mut values.context, &[payment], &[&sender_account]).await?;
+
+    let vault_pda_data = values
+        .context
+        .banks_client
+        .get_account(values.vault_pda_data)
+        .await?
+        .expect("account not found");
+    assert_eq!(vault_pda_data.owner, values.program_id);
+    assert_eq!(vault_pda_data.lamports, values.rent_exemption_lamports);
+    assert_eq!(
+        Payment::unpack(&vault_pda_data.data)?.state,
+        PaymentState::PaymentSent
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_legacy_seed_scheme_receiver_spend() -> Result<(), Box<dyn std::error::Error>> {
+    let mut values = initialize().await?;
+    let legacy_seeds = PaymentSeeds::new(
+        values.lock_time,
+        &values.secret_hash,
+        &values.sender,
+        &values.receiver,
+        None,
+    );
+    (values.vault_pda, values.vault_bump_seed) = Pubkey::find_program_address(
+        &legacy_seeds.seeds(VAULT_SEED, SeedScheme::Legacy, &[]),
+        &values.program_id,
+    );
+    (values.vault_pda_data, values.vault_bump_seed_data) = Pubkey::find_program_address(
+        &legacy_seeds.seeds(VAULT_DATA_SEED, SeedScheme::Legacy, &[]),
+        &values.program_id,
+    );
+
+    let mut hasher = Hasher::default();
+    hasher.hash(values.receiver.as_ref());
+    hasher.hash(values.sender.as_ref());
+    hasher.hash(&values.secret_hash);
+    hasher.hash(values.token_program.as_ref());
+    hasher.hash(&values.amount.to_le_bytes());
+    let payment = Payment {
+        payment_hash: hasher.result().to_bytes(),
+        lock_time: values.lock_time,
+        state: PaymentState::PaymentSent,
+        secret_hash_algorithm: SecretHashAlgorithm::Sha256,
+        lock_time_kind: LockTimeKind::UnixTimestamp,
+        spend_deadline: values.lock_time,
+        terms: None,
+        secret: None,
+        relayer_fee: 0,
+        watcher_reward: 0,
+        dex_fee: 0,
+    };
+    let mut data = vec![0; Payment::LEGACY_LEN];
+    payment.pack_into(&mut data)?;
+    let mut vault_pda_data = AccountSharedData::new(
+        values.rent_exemption_lamports,
+        data.len(),
+        &values.program_id,
+    );
+    vault_pda_data.set_data_from_slice(&data);
+    values
+        .context
+        .set_account(&values.vault_pda_data, &vault_pda_data);
+    values.context.set_account(
+        &values.vault_pda,
+        &AccountSharedData::new(
+            values.amount + values.rent_exemption_lamports,
+            0,
+            &values.system_program,
+        ),
+    );
+
+    let swap_instruction = AtomicSwapInstruction::ReceiverSpend {
+        secret: values.secret.clone(),
+        lock_time: values.lock_time,
+        amount: values.amount,
+        sender: values.sender,
+        token_program: values.token_program,
+        vault_bump_seed: values.vault_bump_seed,
+        vault_bump_seed_data: values.vault_bump_seed_data,
+    };
+    let instruction = Instruction {
+        program_id: values.program_id,
+        accounts: vec![
+            AccountMeta::new(values.receiver_account.pubkey(), true),
+            AccountMeta::new(values.vault_pda_data, false),
+            AccountMeta::new(values.vault_pda, false),
+            AccountMeta::new(values.system_program, false),
+        ],
+        data: swap_instruction.pack(),
+    };
+    let receiver_account = values.receiver_account.insecure_clone();
+    let receiver_account_balance = values
+        .context
+        .banks_client
+        .get_balance(receiver_account.pubkey())
+        .await?;
+
+    // v1 accounts don't store the terms compact instructions need.
+    let compact_spend = Instruction {
+        data: AtomicSwapInstruction::CompactReceiverSpend {
+            secret: values.secret.clone(),
+        }
+        .pack(),
+        ..instruction.clone()
+    };
+    let result =
+        process_instructions(&mut values.context, &[compact_spend], &[&receiver_account]).await;
+    assert_custom_error(result, SwapError::PaymentTermsNotStored);
+
+    process_instructions(&mut values.context, &[instruction], &[&receiver_account]).await?;
+    assert_eq!(
+        values
+            .context
+            .banks_client
+            .get_balance(receiver_account.pubkey())
+            .await?,
+        receiver_account_balance + values.amount
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_payment_vault_address_mismatch() -> Result<(), Box<dyn std::error::Error>> {
+    let mut values = initialize().await?;
+    let sender_account = values.sender_account.insecure_clone();
+
+    let mut payment = lamports_payment_instruction(&values, &sender_account.pubkey());
+    payment.accounts[2].pubkey = Pubkey::new_unique();
+    let result = process_instructions(&mut values.context, &[payment], &[&sender_account]).await;
+    assert_custom_error(result, SwapError::VaultAddressMismatch);
+
+    let mut payment = lamports_payment_instruction(&values, &sender_account.pubkey());
+    payment.accounts[1].pubkey = Pubkey::new_unique();
+    let result = process_instructions(&mut values.context, &[payment], &[&sender_account]).await;
+    assert_custom_error(result, SwapError::VaultDataAddressMismatch);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_spl_payment_vault_address_mismatch() -> Result<(), Box<dyn std::error::Error>> {
+    let mut spl_values = initialize_spl_token(spl_token::id(), &[]).await?;
+    let mut payment = spl_token_payment_instruction(&spl_values);
+    payment.accounts[1].pubkey = Pubkey::new_unique();
+    let values = &mut spl_values.values;
+    let sender_account = values.sender_account.insecure_clone();
+    let result = process_instructions(&mut values.context, &[payment], &[&sender_account]).await;
+    assert_custom_error(result, SwapError::VaultDataAddressMismatch);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_payment_non_canonical_bump() -> Result<(), Box<dyn std::error::Error>> {
+    let mut values = initialize().await?;
+    let payment_seeds = PaymentSeeds::new(
+        values.lock_time,
+        &values.secret_hash,
+        &values.sender,
+        &values.receiver,
+        None,
+    );
+    let (vault_pda_data, vault_bump_seed_data) = (0..values.vault_bump_seed_data)
+        .rev()
+        .find_map(|bump| {
+            let bump_seed = [bump];
+            let seeds = payment_seeds.seeds(VAULT_DATA_SEED, SeedScheme::Bound, &bump_seed);
+            Pubkey::create_program_address(&seeds, &values.program_id)
+                .ok()
+                .map(|address| (address, bump))
+        })
+        .expect("non-canonical bump");
+    values.vault_pda_data = vault_pda_data;
+    values.vault_bump_seed_data = vault_bump_seed_data;
+
+    let sender_account = values.sender_account.insecure_clone();
+    let payment = lamports_payment_instruction(&values, &sender_account.pubkey());
+    let result = process_instructions(&mut values.context, &[payment], &[&sender_account]).await;
+    assert_custom_error(result, SwapError::NonCanonicalBump);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_legacy_payment_layout_ignores_rent_exemption_lamports(
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut values = initialize().await?;
+    let mut data = vec![0];
+    data.extend_from_slice(&values.secret_hash);
+    data.extend_from_slice(&values.lock_time.to_le_bytes());
+    data.extend_from_slice(&values.amount.to_le_bytes());
+    data.extend_from_slice(&values.receiver.to_bytes());
+    data.extend_from_slice(&1u64.to_le_bytes());
+    data.push(values.vault_bump_seed);
+    data.push(values.vault_bump_seed_data);
+
+    let sender_account = values.sender_account.insecure_clone();
+    let mut payment = lamports_payment_instruction(&values, &sender_account.pubkey());
+    payment.data = data;
+    process_instructions(&mut values.context, &[payment], &[&sender_account]).await?;
+
+    assert_eq!(
+        values
+            .context
+            .banks_client
+            .get_balance(values.vault_pda_data)
+            .await?,
+        values.rent_exemption_lamports
+    );
+    Ok(())
+}
+
+#[test]
+fn test_swap_error_codes() {
+    assert_eq!(
+        ProgramError::from(SwapError::InvalidInputLength),
+        ProgramError::Custom(601)
+    );
+    assert_eq!(
+        ProgramError::from(SwapError::VaultPdaNotSystemOwned),
+        ProgramError::Custom(622)
+    );
+    for code in 601..=646 {
+        assert_eq!(SwapError::from_code(code).map(|e| e as u32), Some(code));
+    }
+    assert_eq!(SwapError::from_code(600), None);
+
+    let error = TransactionError::InstructionError(0, InstructionError::Custom(617));
+    let decoded = SwapError::from_transaction_error(&error);
+    assert_eq!(decoded, Some(SwapError::WaitForLockTime));
+    assert_eq!(decoded.unwrap().to_string(), "Lock time has not passed yet");
+    let error = TransactionError::InstructionError(0, InstructionError::InvalidArgument);
+    assert_eq!(SwapError::from_transaction_error(&error), None);
+}
+
+#[test]
+fn test_instruction_encoding() {
+    let receiver = Pubkey::new_unique();
+    let token_program = Pubkey::new_unique();
+    let instructions = [
+        AtomicSwapInstruction::LamportsPayment {
+            secret_hash: [1; 32],
+            secret_hash_algorithm: SecretHashAlgorithm::Hash160,
+            lock_time: 2,
+            lock_time_kind: LockTimeKind::Slot,
+            spend_deadline: 1,
+            amount: 3,
+            relayer_fee: 0,
+            watcher_reward: 0,
+            receiver,
+            vault_bump_seed: 254,
+            vault_bump_seed_data: 253,
+        },
+        AtomicSwapInstruction::SPLTokenPayment {
+            secret_hash: [1; 32],
+            secret_hash_algorithm: SecretHashAlgorithm::Sha256,
+            lock_time: 2,
+            lock_time_kind: LockTimeKind::Epoch,
+            spend_deadline: 1,
+            amount: 3,
+            relayer_fee: 2,
+            watcher_reward: 4,
+            receiver,
+            token_program,
+            vault_bump_seed: 254,
+            vault_bump_seed_data: 253,
+        },
+        AtomicSwapInstruction::ReceiverSpend {
+            secret: vec![1; 32],
+            lock_time: 2,
+            amount: 3,
+            sender: receiver,
+            token_program,
+            vault_bump_seed: 254,
+            vault_bump_seed_data: 253,
+        },
+        AtomicSwapInstruction::SenderRefund {
+            secret_hash: [1; 32],
+            lock_time: 2,
+            amount: 3,
+            receiver,
+            token_program,
+            vault_bump_seed: 254,
+            vault_bump_seed_data: 253,
+        },
+        AtomicSwapInstruction::ClosePayment {
+            secret_hash: [1; 32],
+            lock_time: 2,
+            amount: 3,
+            receiver,
+            token_program,
+            vault_bump_seed: 254,
+            vault_bump_seed_data: 253,
+        },
+        AtomicSwapInstruction::CompactReceiverSpend {
+            secret: vec![1; 32],
+        },
+        AtomicSwapInstruction::RelayedReceiverSpend {
+            secret: vec![1; 20],
+            lock_time: 2,
+            amount: 3,
+            sender: receiver,
+            token_program,
+            vault_bump_seed: 254,
+            vault_bump_seed_data: 253,
+        },
+        AtomicSwapInstruction::PaymentWithDexFee {
+            dex_fee: 5,
+            payment: Box::new(AtomicSwapInstruction::SPLTokenPayment {
+                secret_hash: [1; 32],
+                secret_hash_algorithm: SecretHashAlgorithm::Sha256,
+                lock_time: 2,
+                lock_time_kind: LockTimeKind::UnixTimestamp,
+                spend_deadline: 2,
+                amount: 3,
+                relayer_fee: 0,
+                watcher_reward: 0,
+                receiver,
+                token_program,
+                vault_bump_seed: 254,
+                vault_bump_seed_data: 253,
+            }),
+        },
+        AtomicSwapInstruction::RelayedSenderRefund {
+            secret_hash: [1; 32],
+            lock_time: 2,
+            amount: 3,
+            receiver,
+            token_program,
+            vault_bump_seed: 254,
+            vault_bump_seed_data: 253,
+        },
+        AtomicSwapInstruction::QueryPayment {
+            secret_hash: [1; 32],
+            lock_time: 2,
+            amount: 3,
+            sender: receiver,
+            receiver,
+            mint: token_program,
+            token_program,
+        },
+        AtomicSwapInstruction::InitializeConfig {
+            admin: receiver,
+            settings: ConfigSettings {
+                dex_fee_account: receiver,
+                dex_fee_rate: 10,
+                min_amount: 1000,
+                allowed_token_programs: vec![token_program],
+            },
+        },
+        AtomicSwapInstruction::UpdateConfig {
+            settings: ConfigSettings::default(),
+        },
+        AtomicSwapInstruction::ProposeConfigAdmin { admin: receiver },
+        AtomicSwapInstruction::SetPaused { paused: true },
+        AtomicSwapInstruction::AcceptConfigAdmin,
+    ];
+    for swap_instruction in instructions {
+        let data = swap_instruction.pack();
+        assert_eq!(
+            data[..2],
+            [
+                instruction::VERSIONED_INSTRUCTION,
+                instruction::INSTRUCTION_VERSION
+            ]
+        );
+        assert_eq!(AtomicSwapInstruction::unpack(&data), Ok(swap_instruction));
+
+        let truncated = AtomicSwapInstruction::unpack(&data[..data.len() - 3]);
+        assert_eq!(truncated, Err(SwapError::InvalidInputLength.into()));
+        let mut extended = data.clone();
+        extended.push(0);
+        let extended = AtomicSwapInstruction::unpack(&extended);
+        assert_eq!(extended, Err(SwapError::InvalidInputLength.into()));
+    }
+
+    // The versioned payments carry the secret hash algorithm and the lock time kind after the bumps.
+    let payment = AtomicSwapInstruction::LamportsPayment {
+        secret_hash: [1; 32],
+        secret_hash_algorithm: SecretHashAlgorithm::Sha256,
+        lock_time: 2,
+        lock_time_kind: LockTimeKind::UnixTimestamp,
+        spend_deadline: 2,
+        amount: 3,
+        relayer_fee: 0,
+        watcher_reward: 0,
+        receiver,
+        vault_bump_seed: 254,
+        vault_bump_seed_data: 253,
+    };
+    let mut data = payment.pack();
+    let len = data.len();
+    data[len - 25] = 0xff;
+    assert_eq!(
+        AtomicSwapInstruction::unpack(&data),
+        Err(SwapError::InvalidLockTime.into())
+    );
+    data[len - 26] = 0xff;
+    assert_eq!(
+        AtomicSwapInstruction::unpack(&data),
+        Err(SwapError::InvalidSecretHashAlgorithm.into())
+    );
+
+    // v1 spend layout, still sent by deployed clients.
+    let mut data = vec![2];
+    data.extend_from_slice(&[1; 32]);
+    data.extend_from_slice(&2u64.to_le_bytes());
+    data.extend_from_slice(&3u64.to_le_bytes());
+    data.extend_from_slice(receiver.as_ref());
+    data.extend_from_slice(token_program.as_ref());
+    data.extend_from_slice(&[254, 253]);
+    assert_eq!(
+        AtomicSwapInstruction::unpack(&data),
+        Ok(AtomicSwapInstruction::ReceiverSpend {
+            secret: vec![1; 32],
+            lock_time: 2,
+            amount: 3,
+            sender: receiver,
+            token_program,
+            vault_bump_seed: 254,
+            vault_bump_seed_data: 253,
+        })
+    );
+
+    // Versioned spends carry a length-prefixed secret, v1 spends 32 bytes.
+    let spend = |secret: Vec<u8>| AtomicSwapInstruction::ReceiverSpend {
+        secret,
+        lock_time: 2,
+        amount: 3,
+        sender: receiver,
+        token_program,
+        vault_bump_seed: 254,
+        vault_bump_seed_data: 253,
+    };
+    for len in [16, 20, instruction::MAX_SECRET_LEN] {
+        let data = spend(vec![1; len]).pack();
+        assert_eq!(data[3] as usize, len);
+        assert_eq!(
+            AtomicSwapInstruction::unpack(&data),
+            Ok(spend(vec![1; len]))
+        );
+    }
+    let refund = AtomicSwapInstruction::CompactSenderRefund.pack();
+    assert_eq!(
+        AtomicSwapInstruction::unpack(&refund),
+        Ok(AtomicSwapInstruction::CompactSenderRefund)
+    );
+    // Only payments can be wrapped with a dex fee.
+    let mut data = AtomicSwapInstruction::PaymentWithDexFee {
+        dex_fee: 5,
+        payment: Box::new(AtomicSwapInstruction::CompactSenderRefund),
+    }
+    .pack();
+    assert_eq!(data[11], 6);
+    assert_eq!(
+        AtomicSwapInstruction::unpack(&data),
+        Err(SwapError::InvalidAtomicSwapInstruction.into())
+    );
+    data[11] = 10;
+    assert_eq!(
+        AtomicSwapInstruction::unpack(&data),
+        Err(SwapError::InvalidAtomicSwapInstruction.into())
+    );
+    let too_long = spend(vec![1; instruction::MAX_SECRET_LEN + 1]).pack();
+    assert_eq!(
+        AtomicSwapInstruction::unpack(&too_long),
+        Err(SwapError::SecretTooLong.into())
+    );
+    assert_eq!(
+        AtomicSwapInstruction::unpack(&spend(vec![]).pack()),
+        Err(SwapError::InvalidSecret.into())
+    );
+
+    assert_eq!(
+        AtomicSwapInstruction::unpack(&[]),
+        Err(SwapError::InvalidInputLength.into())
+    );
+    assert_eq!(
+        AtomicSwapInstruction::unpack(&[instruction::VERSIONED_INSTRUCTION]),
+        Err(SwapError::InvalidInputLength.into())
+    );
+    assert_eq!(
+        AtomicSwapInstruction::unpack(&[
+            instruction::VERSIONED_INSTRUCTION,
+            instruction::INSTRUCTION_VERSION + 1,
+            0
+        ]),
+        Err(SwapError::UnsupportedInstructionVersion.into())
+    );
+    assert_eq!(
+        AtomicSwapInstruction::unpack(&[
+            instruction::VERSIONED_INSTRUCTION,
+            instruction::INSTRUCTION_VERSION,
+            16
+        ]),
+        Err(SwapError::InvalidAtomicSwapInstruction.into())
+    );
+    // Unversioned tags stop at the v1 instructions, so none aliases a versioned one.
+    for tag in [5, 6, 7] {
+        assert_eq!(
+            AtomicSwapInstruction::unpack(&[tag]),
+            Err(SwapError::InvalidAtomicSwapInstruction.into())
+        );
+    }
+    // Nor do v1 payments without the rent exemption lamports.
+    let mut data = vec![5];
+    data.extend_from_slice(&[1; 32]);
+    data.extend_from_slice(&2u64.to_le_bytes());
+    data.extend_from_slice(&3u64.to_le_bytes());
+    data.extend_from_slice(receiver.as_ref());
+    data.extend_from_slice(&[254, 253]);
+    assert_eq!(
+        AtomicSwapInstruction::unpack(&data),
+        Err(SwapError::InvalidAtomicSwapInstruction.into())
+    );
+}
+
+#[test]
+fn test_hash160_secret_hash() {
+    // Bitcoin wiki example: the P2PKH hash of this compressed public key.
+    let pubkey =
+        hex::decode("0250863ad64a87ae8a2fe83c1af1a8403cb53f53e486d8511dad8a04887e5b2352").unwrap();
+    let mut expected = [0; 32];
+    expected[..20]
+        .copy_from_slice(&hex::decode("f54a5851e9372b87810a8e60cdd2e7cfd80b6e31").unwrap());
+    assert_eq!(SecretHashAlgorithm::Hash160.hash_secret(&pubkey), expected);
+}
+
+fn set_secret_hash(
+    values: &mut InitializeValues,
+    secret_hash: [u8; 32],
+    secret_hash_algorithm: SecretHashAlgorithm,
+) {
+    values.secret_hash = secret_hash;
+    values.secret_hash_algorithm = secret_hash_algorithm;
+    (
+        (values.vault_pda, values.vault_bump_seed),
+        (values.vault_pda_data, values.vault_bump_seed_data),
+    ) = find_vault_addresses(
+        &values.program_id,
+        values.lock_time,
+        &values.secret_hash,
+        &values.sender,
+        &values.receiver,
+        None,
+    );
+}
+
+async fn receiver_spend_with_algorithm(
+    secret_hash_algorithm: SecretHashAlgorithm,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut values = initialize().await?;
+    let secret_hash = secret_hash_algorithm.hash_secret(&values.secret);
+    set_secret_hash(&mut values, secret_hash, secret_hash_algorithm);
+
+    let sender_account = values.sender_account.insecure_clone();
+    let payment =
+        instruction::lamports_payment(&values.program_id, &values.sender, &new_payment(&values));
+    process_instructions(&mut values.context, &[payment], &[&sender_account]).await?;
+
+    let receiver_account = values.receiver_account.insecure_clone();
+    let receiver_account_balance = values
+        .context
+        .banks_client
+        .get_balance(receiver_account.pubkey())
+        .await?;
+    let spend = instruction::receiver_spend(
+        &values.program_id,
+        &values.receiver,
+        &values.sender,
+        &values.secret,
+        values.secret_hash_algorithm,
+        values.lock_time,
+        values.amount,
+        None,
+    )
+    .expect("valid secret");
+    process_instructions(&mut values.context, &[spend], &[&receiver_account]).await?;
+
+    assert_eq!(
+        values
+            .context
+            .banks_client
+            .get_balance(receiver_account.pubkey())
+            .await?,
+        receiver_account_balance + values.amount
+    );
+    let vault_pda_data = values
+        .context
+        .banks_client
+        .get_account(values.vault_pda_data)
+        .await?
+        .expect("account not found");
+    let payment = Payment::unpack(&vault_pda_data.data)?;
+    assert_eq!(payment.state, PaymentState::ReceiverSpent);
+    assert_eq!(payment.secret_hash_algorithm, secret_hash_algorithm);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_hash160_receiver_spend() -> Result<(), Box<dyn std::error::Error>> {
+    receiver_spend_with_algorithm(SecretHashAlgorithm::Hash160).await
+}
+
+#[test]
+fn test_keccak256_secret_hash() {
+    assert_eq!(
+        hex::encode(SecretHashAlgorithm::Keccak256.hash_secret(&[])),
+        "c5d2460186f7233c927e7db2dcc703c0e500b653ca82273b7bfad8045d85a470"
+    );
+    assert_eq!(
+        hex::encode(SecretHashAlgorithm::Keccak256.hash_secret(&[0; 32])),
+        "290decd9548b62a8d60345a988386fc84ba6bc95484008f6362f93160ef3e563"
+    );
+}
+
+#[tokio::test]
+async fn test_keccak256_receiver_spend() -> Result<(), Box<dyn std::error::Error>> {
+    receiver_spend_with_algorithm(SecretHashAlgorithm::Keccak256).await
+}
+
+#[tokio::test]
+async fn test_receiver_spend_other_secret_hash_algorithm() -> Result<(), Box<dyn std::error::Error>>
+{
+    // A Keccak-256 payment committing to the SHA-256 hash of the secret.
+    let mut values = initialize().await?;
+    let secret_hash = values.secret_hash;
+    set_secret_hash(&mut values, secret_hash, SecretHashAlgorithm::Keccak256);
+    let sender_account = values.sender_account.insecure_clone();
+    let payment =
+        instruction::lamports_payment(&values.program_id, &values.sender, &new_payment(&values));
+    process_instructions(&mut values.context, &[payment], &[&sender_account]).await?;
+
+    let spend = instruction::receiver_spend(
+        &values.program_id,
+        &values.receiver,
+        &values.sender,
+        &values.secret,
+        SecretHashAlgorithm::Sha256,
+        values.lock_time,
+        values.amount,
+        None,
+    )
+    .expect("valid secret");
+    let receiver_account = values.receiver_account.insecure_clone();
+    let result =
+        process_instructions(&mut values.context, &[spend.clone()], &[&receiver_account]).await;
+    assert_custom_error(result, SwapError::VaultAddressMismatch);
+
+    // Rewriting the stored algorithm does not help, the payment hash commits to it.
+    let mut vault_pda_data = values
+        .context
+        .banks_client
+        .get_account(values.vault_pda_data)
+        .await?
+        .expect("account not found");
+    let mut payment = Payment::unpack(&vault_pda_data.data)?;
+    payment.secret_hash_algorithm = SecretHashAlgorithm::Sha256;
+    payment.pack_into(&mut vault_pda_data.data)?;
+    values
+        .context
+        .set_account(&values.vault_pda_data, &vault_pda_data.into());
+    let result = process_instructions(&mut values.context, &[spend], &[&receiver_account]).await;
+    assert_custom_error(result, SwapError::InvalidPaymentHash);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_receiver_spend_variable_length_secret() -> Result<(), Box<dyn std::error::Error>> {
+    for (len, secret_hash_algorithm) in [
+        (16, SecretHashAlgorithm::Sha256),
+        (20, SecretHashAlgorithm::Hash160),
+        (instruction::MAX_SECRET_LEN, SecretHashAlgorithm::Keccak256),
+    ] {
+        let mut values = initialize().await?;
+        values.secret = vec![7; len];
+        let secret_hash = secret_hash_algorithm.hash_secret(&values.secret);
+        set_secret_hash(&mut values, secret_hash, secret_hash_algorithm);
+        let sender_account = values.sender_account.insecure_clone();
+        let payment = instruction::lamports_payment(
+            &values.program_id,
+            &values.sender,
+            &new_payment(&values),
+        );
+        process_instructions(&mut values.context, &[payment], &[&sender_account]).await?;
+
+        let spend = instruction::receiver_spend(
+            &values.program_id,
+            &values.receiver,
+            &values.sender,
+            &values.secret,
+            values.secret_hash_algorithm,
+            values.lock_time,
+            values.amount,
+            None,
+        )
+        .expect("valid secret");
+        let receiver_account = values.receiver_account.insecure_clone();
+        process_instructions(&mut values.context, &[spend], &[&receiver_account]).await?;
+        let vault_pda_data = values
+            .context
+            .banks_client
+            .get_account(values.vault_pda_data)
+            .await?
+            .expect("account not found");
+        let payment = Payment::unpack(&vault_pda_data.data)?;
+        assert_eq!(payment.state, PaymentState::ReceiverSpent);
+        assert_eq!(payment.revealed_secret(), Some(values.secret.as_slice()));
+    }
+    Ok(())
+}
+
+#[test]
+fn test_receiver_spend_invalid_secret() {
+    let program_id = Pubkey::new_unique();
+    let (sender, receiver) = (Pubkey::new_unique(), Pubkey::new_unique());
+    let spend = |secret: &[u8]| {
+        instruction::receiver_spend(
+            &program_id,
+            &receiver,
+            &sender,
+            secret,
+            SecretHashAlgorithm::Sha256,
+            1,
+            2,
+            None,
+        )
+    };
+    assert!(spend(&[1; instruction::MAX_SECRET_LEN]).is_some());
+    // Secrets the length prefix can't hold or the program rejects.
+    assert!(spend(&[1; 256]).is_none());
+    assert!(spend(&[1; instruction::MAX_SECRET_LEN + 1]).is_none());
+    assert!(spend(&[]).is_none());
+}
+
+#[test]
+fn test_payment_layouts() -> Result<(), ProgramError> {
+    let payment = Payment {
+        payment_hash: [1; 32],
+        lock_time: 2,
+        state: PaymentState::PaymentSent,
+        secret_hash_algorithm: SecretHashAlgorithm::Sha256,
+        lock_time_kind: LockTimeKind::UnixTimestamp,
+        spend_deadline: 2,
+        terms: None,
+        secret: None,
+        relayer_fee: 0,
+        watcher_reward: 0,
+        dex_fee: 0,
+    };
+    let mut data = vec![0; Payment::LEGACY_LEN];
+    payment.pack_into(&mut data)?;
+    assert_eq!(Payment::unpack(&data)?, payment);
+    // Accounts are either v1 or v2, nothing in between.
+    for len in [
+        Payment::LEGACY_LEN - 1,
+        Payment::LEGACY_LEN + 1,
+        Payment::VERSION_OFFSET,
+        Payment::VERSION_OFFSET + 1,
+    ] {
+        let mut data = vec![0; len];
+        assert_eq!(
+            payment.pack_into(&mut data),
+            Err(ProgramError::InvalidAccountData)
+        );
+        assert_eq!(
+            Payment::unpack(&data),
+            Err(ProgramError::InvalidAccountData)
+        );
+    }
+
+    // v2 accounts store the terms after a version byte, v1 accounts cannot hold them.
+    let mut v2_payment = payment.clone();
+    v2_payment.terms = Some(PaymentTerms {
+        sender: Pubkey::new_unique(),
+        receiver: Pubkey::new_unique(),
+        mint: Pubkey::new_unique(),
+        token_program: Pubkey::new_unique(),
+        secret_hash: [3; 32],
+        amount: 4,
+        vault_bump_seed: 254,
+        vault_bump_seed_data: 253,
+        created_at: -5,
+    });
+    let mut data = vec![0; Payment::LEN];
+    v2_payment.pack_into(&mut data)?;
+    assert_eq!(data[Payment::VERSION_OFFSET], Payment::V2);
+    assert_eq!(Payment::unpack(&data)?, v2_payment);
+    data[Payment::VERSION_OFFSET] = Payment::V2 + 1;
+    assert_eq!(
+        Payment::unpack(&data),
+        Err(ProgramError::InvalidAccountData)
+    );
+    let mut data = vec![0; Payment::LEGACY_LEN];
+    assert_eq!(
+        v2_payment.pack_into(&mut data),
+        Err(ProgramError::InvalidAccountData)
+    );
+    assert_eq!(
+        Payment::unpack(&vec![0; Payment::LEN - 1]),
+        Err(ProgramError::InvalidAccountData)
+    );
+
+    // The revealed secret, the relayer fee, the watcher reward and the dex fee follow the terms.
+    v2_payment.state = PaymentState::ReceiverSpent;
+    v2_payment.secret = Some(vec![6; 20]);
+    v2_payment.relayer_fee = 7;
+    v2_payment.watcher_reward = 9;
+    v2_payment.dex_fee = 10;
+    let mut data = vec![0; Payment::LEN];
+    v2_payment.pack_into(&mut data)?;
+    assert_eq!(data[Payment::SECRET_OFFSET], 20);
+    let stored = Payment::unpack(&data)?;
+    assert_eq!(stored, v2_payment);
+    assert_eq!(stored.revealed_secret(), Some(&[6; 20][..]));
+    // v2 accounts always hold every field.
+    for len in [
+        Payment::SECRET_OFFSET,
+        Payment::LEN - 24,
+        Payment::LEN - 16,
+        Payment::LEN - 8,
+    ] {
+        assert_eq!(
+            Payment::unpack(&data[..len]),
+            Err(ProgramError::InvalidAccountData)
+        );
+    }
+    data[Payment::SECRET_OFFSET] = instruction::MAX_SECRET_LEN as u8 + 1;
+    assert_eq!(
+        Payment::unpack(&data),
+        Err(ProgramError::InvalidAccountData)
+    );
+
+    // v1 accounts cannot hold any field they predate at a non-default value.
+    let mut data = vec![0; Payment::LEGACY_LEN];
+    for update in [
+        |payment: &mut Payment| payment.secret_hash_algorithm = SecretHashAlgorithm::Hash160,
+        |payment: &mut Payment| payment.lock_time_kind = LockTimeKind::Slot,
+        |payment: &mut Payment| payment.spend_deadline = 1,
+    ] {
+        let mut payment = payment.clone();
+        update(&mut payment);
+        assert_eq!(
+            payment.pack_into(&mut data),
+            Err(ProgramError::InvalidAccountData)
+        );
+    }
+    Ok(())
+}
+
+#[test]
+fn test_lock_time_helpers() {
+    assert_eq!(lock_time::slot_lock_time(100, Duration::from_secs(4)), 120);
+    assert_eq!(
+        lock_time::slot_lock_time(100, Duration::from_millis(4001)),
+        121
+    );
+    let epoch_schedule = EpochSchedule::default();
+    assert_eq!(
+        lock_time::epoch_lock_time(&epoch_schedule, 0, Duration::from_secs(3600)),
+        epoch_schedule.get_epoch(18000)
+    );
+}
+
+#[tokio::test]
+async fn test_slot_lock_time_sender_refund() -> Result<(), Box<dyn std::error::Error>> {
+    let mut values = initialize().await?;
+    let current_slot = values.context.banks_client.get_root_slot().await?;
+    values.lock_time = lock_time::slot_lock_time(current_slot, Duration::from_secs(40));
+    values.lock_time_kind = LockTimeKind::Slot;
+    let (secret_hash, secret_hash_algorithm) = (values.secret_hash, values.secret_hash_algorithm);
+    set_secret_hash(&mut values, secret_hash, secret_hash_algorithm);
+
+    let sender_account = values.sender_account.insecure_clone();
+    let payment =
+        instruction::lamports_payment(&values.program_id, &values.sender, &new_payment(&values));
+    process_instructions(&mut values.context, &[payment], &[&sender_account]).await?;
+
+    let refund = sender_refund_instruction(&values);
+    let result =
+        process_instructions(&mut values.context, &[refund.clone()], &[&sender_account]).await;
+    assert_custom_error(result, SwapError::WaitForLockTime);
+
+    values.context.warp_to_slot(values.lock_time + 1)?;
+    process_instructions(&mut values.context, &[refund], &[&sender_account]).await?;
+    let vault_pda_data = values
+        .context
+        .banks_client
+        .get_account(values.vault_pda_data)
+        .await?
+        .expect("account not found");
+    let payment = Payment::unpack(&vault_pda_data.data)?;
+    assert_eq!(payment.state, PaymentState::SenderRefunded);
+    assert_eq!(payment.lock_time_kind, LockTimeKind::Slot);
+    Ok(())
+}
+
+fn receiver_spend_instruction(values: &InitializeValues) -> Instruction {
+    instruction::receiver_spend(
+        &values.program_id,
+        &values.receiver,
+        &values.sender,
+        &values.secret,
+        values.secret_hash_algorithm,
+        values.lock_time,
+        values.amount,
+        None,
+    )
+    .expect("valid secret")
+}
+
+#[tokio::test]
+async fn test_receiver_spend_at_lock_time() -> Result<(), Box<dyn std::error::Error>> {
+    // Without an explicit deadline the receiver can spend up to and including the lock time, the
+    // sender can only refund once it has passed.
+    let mut values = submit_payment().await?;
+    set_unix_timestamp(&mut values.context, values.lock_time).await?;
+    let sender_account = values.sender_account.insecure_clone();
+    let refund = sender_refund_instruction(&values);
+    let result = process_instructions(&mut values.context, &[refund], &[&sender_account]).await;
+    assert_custom_error(result, SwapError::WaitForLockTime);
+
+    let receiver_account = values.receiver_account.insecure_clone();
+    let spend = receiver_spend_instruction(&values);
+    process_instructions(&mut values.context, &[spend], &[&receiver_account]).await?;
+    let vault_pda_data = values
+        .context
+        .banks_client
+        .get_account(values.vault_pda_data)
+        .await?
+        .expect("account not found");
+    let payment = Payment::unpack(&vault_pda_data.data)?;
+    assert_eq!(payment.state, PaymentState::ReceiverSpent);
+    assert_eq!(payment.spend_deadline, values.lock_time);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_receiver_spend_after_deadline() -> Result<(), Box<dyn std::error::Error>> {
+    let mut values = initialize().await?;
+    let spend_deadline = values.lock_time - 500;
+    values.spend_deadline = Some(spend_deadline);
+    let sender_account = values.sender_account.insecure_clone();
+    let payment =
+        instruction::lamports_payment(&values.program_id, &values.sender, &new_payment(&values));
+    process_instructions(&mut values.context, &[payment], &[&sender_account]).await?;
+
+    // Between the deadline and the lock time neither party can move the funds.
+    set_unix_timestamp(&mut values.context, spend_deadline + 1).await?;
+    let receiver_account = values.receiver_account.insecure_clone();
+    let spend = receiver_spend_instruction(&values);
+    let result = process_instructions(&mut values.context, &[spend], &[&receiver_account]).await;
+    assert_custom_error(result, SwapError::SpendDeadlinePassed);
+    let refund = sender_refund_instruction(&values);
+    let result =
+        process_instructions(&mut values.context, &[refund.clone()], &[&sender_account]).await;
+    assert_custom_error(result, SwapError::WaitForLockTime);
+
+    // Warping refreshes the blockhash so the refund is not rejected as already processed.
+    let current_slot = values.context.banks_client.get_root_slot().await?;
+    values.context.warp_to_slot(current_slot + 2)?;
+    set_unix_timestamp(&mut values.context, values.lock_time + 1).await?;
+    process_instructions(&mut values.context, &[refund], &[&sender_account]).await?;
+    let vault_pda_data = values
+        .context
+        .banks_client
+        .get_account(values.vault_pda_data)
+        .await?
+        .expect("account not found");
+    let payment = Payment::unpack(&vault_pda_data.data)?;
+    assert_eq!(payment.state, PaymentState::SenderRefunded);
+    assert_eq!(payment.spend_deadline, spend_deadline);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_payment_spend_deadline_after_lock_time() -> Result<(), Box<dyn std::error::Error>> {
+    let mut values = initialize().await?;
+    let sender_account = values.sender_account.insecure_clone();
+    let payment = instruction::lamports_payment(
+        &values.program_id,
+        &values.sender,
+        &instruction::NewPayment {
+            spend_deadline: Some(values.lock_time + 1),
+            ..new_payment(&values)
+        },
+    );
+    let result = process_instructions(&mut values.context, &[payment], &[&sender_account]).await;
+    assert_custom_error(result, SwapError::InvalidSpendDeadline);
+    Ok(())
+}
+
+async fn stored_payment(
+    values: &mut InitializeValues,
+) -> Result<Payment, Box<dyn std::error::Error>> {
+    let vault_pda_data = values
+        .context
+        .banks_client
+        .get_account(values.vault_pda_data)
+        .await?
+        .expect("account not found");
+    Ok(Payment::unpack(&vault_pda_data.data)?)
+}
+
+#[tokio::test]
+async fn test_compact_receiver_spend() -> Result<(), Box<dyn std::error::Error>> {
+    let mut values = submit_payment().await?;
+    let payment = stored_payment(&mut values).await?;
+    let clock = values.context.banks_client.get_sysvar::<Clock>().await?;
+    assert_eq!(
+        payment.terms,
+        Some(PaymentTerms {
+            sender: values.sender,
+            receiver: values.receiver,
+            mint: Pubkey::default(),
+            token_program: Pubkey::default(),
+            secret_hash: values.secret_hash,
+            amount: values.amount,
+            vault_bump_seed: values.vault_bump_seed,
+            vault_bump_seed_data: values.vault_bump_seed_data,
+            created_at: clock.unix_timestamp,
+        })
+    );
+
+    let receiver_account = values.receiver_account.insecure_clone();
+    let receiver_account_balance = values
+        .context
+        .banks_client
+        .get_balance(receiver_account.pubkey())
+        .await?;
+    let spend =
+        instruction::compact_receiver_spend(&values.program_id, &payment, &values.secret, None)
+            .expect("payment stores its terms");
+    assert_eq!(spend.accounts, receiver_spend_instruction(&values).accounts);
+
+    // The secret still has to hash to the stored secret hash.
+    let wrong_secret =
+        instruction::compact_receiver_spend(&values.program_id, &payment, &[1; 32], None)
+            .expect("payment stores its terms");
+    let result =
+        process_instructions(&mut values.context, &[wrong_secret], &[&receiver_account]).await;
+    assert_custom_error(result, SwapError::VaultAddressMismatch);
+
+    process_instructions(&mut values.context, &[spend], &[&receiver_account]).await?;
+    assert_eq!(
+        values
+            .context
+            .banks_client
+            .get_balance(receiver_account.pubkey())
+            .await?,
+        receiver_account_balance + values.amount
+    );
+    let payment = stored_payment(&mut values).await?;
+    assert_eq!(payment.state, PaymentState::ReceiverSpent);
+    assert_eq!(payment.revealed_secret(), Some(values.secret.as_slice()));
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_compact_sender_refund() -> Result<(), Box<dyn std::error::Error>> {
+    let mut values = submit_payment().await?;
+    let payment = stored_payment(&mut values).await?;
+    let sender_account = values.sender_account.insecure_clone();
+    let refund = instruction::compact_sender_refund(&values.program_id, &payment, None)
+        .expect("payment stores its terms");
+    assert_eq!(refund.accounts, sender_refund_instruction(&values).accounts);
+
+    // Only the stored sender can refund.
+    let receiver_account = values.receiver_account.insecure_clone();
+    let mut foreign_refund = refund.clone();
+    foreign_refund.accounts[0].pubkey = receiver_account.pubkey();
+    set_unix_timestamp(&mut values.context, values.lock_time + 1).await?;
+    let result =
+        process_instructions(&mut values.context, &[foreign_refund], &[&receiver_account]).await;
+    assert_custom_error(result, SwapError::VaultAddressMismatch);
+
+    let sender_account_balance = values
+        .context
+        .banks_client
+        .get_balance(sender_account.pubkey())
+        .await?;
+    process_instructions(&mut values.context, &[refund], &[&sender_account]).await?;
+    assert_eq!(
+        values
+            .context
+            .banks_client
+            .get_balance(sender_account.pubkey())
+            .await?,
+        sender_account_balance + values.amount
+    );
+    let payment = stored_payment(&mut values).await?;
+    assert_eq!(payment.state, PaymentState::SenderRefunded);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_spl_compact_receiver_spend() -> Result<(), Box<dyn std::error::Error>> {
+    let mut spl_values =
+        submit_spl_token_payment_with(spl_token_2022::id(), &[ExtensionType::TransferFeeConfig])
+            .await?;
+    let payment = stored_payment(&mut spl_values.values).await?;
+    let terms = payment.terms.expect("payment stores its terms");
+    assert_eq!(terms.mint, spl_values.mint);
+    assert_eq!(terms.token_program, spl_token_2022::id());
+    assert_eq!(terms.amount, spl_values.escrowed_amount);
+
+    let token_accounts = spl_values.token_accounts(spl_values.receiver_token_account);
+    let values = &mut spl_values.values;
+    let spend = instruction::compact_receiver_spend(
+        &values.program_id,
+        &payment,
+        &values.secret,
+        Some(&token_accounts),
+    )
+    .expect("payment stores its terms");
+    let receiver_account = values.receiver_account.insecure_clone();
+    process_instructions(&mut values.context, &[spend], &[&receiver_account]).await?;
+
+    let context = &mut spl_values.values.context;
+    assert_eq!(
+        get_token_balance(context, &spl_values.vault_token_account).await?,
+        0
+    );
+    let escrowed_amount = spl_values.escrowed_amount;
+    assert_eq!(
+        get_token_balance(context, &spl_values.receiver_token_account).await?,
+        escrowed_amount - escrowed_amount * TRANSFER_FEE_BASIS_POINTS as u64 / 10_000
+    );
+    Ok(())
+}
+
+/// Simulates `query` and decodes the `PaymentStatus` it returned.
+async fn query_payment_status(
+    context: &mut ProgramTestContext,
+    query: Instruction,
+) -> Result<PaymentStatus, Box<dyn std::error::Error>> {
+    let mut transaction = Transaction::new_with_payer(&[query], Some(&context.payer.pubkey()));
+    transaction.sign(&[&context.payer], context.last_blockhash);
+    let simulation = context
+        .banks_client
+        .simulate_transaction(transaction)
+        .await?;
+    if let Some(Err(error)) = simulation.result {
+        return Err(error.into());
+    }
+    let return_data = simulation
+        .simulation_details
+        .and_then(|details| details.return_data)
+        .expect("query returns data");
+    Ok(PaymentStatus::unpack(&return_data.data)?)
+}
+
+#[tokio::test]
+async fn test_query_payment() -> Result<(), Box<dyn std::error::Error>> {
+    let mut values = submit_payment().await?;
+    let query = instruction::query_payment(
+        &values.program_id,
+        &values.sender,
+        &values.receiver,
+        values.secret_hash,
+        values.lock_time,
+        values.amount,
+        None,
+    );
+    let status = query_payment_status(&mut values.context, query.clone()).await?;
+    assert_eq!(
+        status,
+        PaymentStatus {
+            state: PaymentState::PaymentSent,
+            lock_time: values.lock_time,
+            lock_time_kind: LockTimeKind::UnixTimestamp,
+            spend_deadline: values.lock_time,
+            spendable: true,
+            refundable: false,
+        }
+    );
+
+    let wrong_amount = instruction::query_payment(
+        &values.program_id,
+        &values.sender,
+        &values.receiver,
+        values.secret_hash,
+        values.lock_time,
+        values.amount + 1,
+        None,
+    );
+    let error = query_payment_status(&mut values.context, wrong_amount)
+        .await
+        .expect_err("amount is committed to by the payment hash");
+    let error = error.downcast::<TransactionError>()?;
+    assert_eq!(
+        SwapError::from_transaction_error(&error),
+        Some(SwapError::InvalidPaymentHash)
+    );
+
+    set_unix_timestamp(&mut values.context, values.lock_time + 1).await?;
+    let status = query_payment_status(&mut values.context, query.clone()).await?;
+    assert!(!status.spendable);
+    assert!(status.refundable);
+
+    let sender_account = values.sender_account.insecure_clone();
+    let refund = sender_refund_instruction(&values);
+    process_instructions(&mut values.context, &[refund], &[&sender_account]).await?;
+    let status = query_payment_status(&mut values.context, query.clone()).await?;
+    assert_eq!(status.state, PaymentState::SenderRefunded);
+    assert!(!status.refundable);
+    Ok(())
+}
+
+#[test]
+fn test_swap_event_encoding() {
+    let event = SwapEvent {
+        kind: SwapEventKind::ReceiverSpent {
+            secret: vec![1; 20],
+        },
+        payment: Pubkey::new_unique(),
+        payment_hash: [2; 32],
+        amount: 3,
+        mint: Pubkey::new_unique(),
+    };
+    let data = event.pack();
+    assert_eq!(data[0], event::EVENT_VERSION);
+    assert_eq!(SwapEvent::unpack(&data), Ok(event.clone()));
+    for invalid in [&data[..data.len() - 1], &data[..106]] {
+        assert_eq!(
+            SwapEvent::unpack(invalid),
+            Err(ProgramError::InvalidAccountData)
+        );
+    }
+    let mut unknown_version = data.clone();
+    unknown_version[0] = event::EVENT_VERSION + 1;
+    assert_eq!(
+        SwapEvent::unpack(&unknown_version),
+        Err(ProgramError::InvalidAccountData)
+    );
+
+    // program-test prints the data logged by builtin programs instead of recording it, so the
+    // decoder is fed the log messages a validator produces. Data logged by invoked programs is
+    // skipped.
+    let log_data = |event: &SwapEvent| {
+        use base64::Engine;
+        format!(
+            "Program data: {}",
+            base64::engine::general_purpose::STANDARD.encode(event.pack())
+        )
+    };
+    let program_id = Pubkey::new_unique();
+    let token_program = spl_token::id();
+    let sent = SwapEvent {
+        kind: SwapEventKind::PaymentSent { dex_fee: 4 },
+        ..event.clone()
+    };
+    let data = sent.pack();
+    assert_eq!(SwapEvent::unpack(&data), Ok(sent));
+    for invalid in [&data[..data.len() - 1], &data[..106]] {
+        assert_eq!(
+            SwapEvent::unpack(invalid),
+            Err(ProgramError::InvalidAccountData)
+        );
+    }
+    let refunded = SwapEvent {
+        kind: SwapEventKind::SenderRefunded,
+        ..event.clone()
+    };
+    let logs = [
+        format!("Program {} invoke [1]", program_id),
+        format!("Program {} invoke [2]", token_program),
+        log_data(&refunded),
+        format!("Program {} success", token_program),
+        log_data(&event),
+        "Program data: not base64".to_string(),
+        format!("Program {} success", program_id),
+        log_data(&refunded),
+        format!("Program {} invoke [1]", program_id),
+        log_data(&refunded),
+        format!("Program {} failed: custom program error: 0x259", program_id),
+    ];
+    assert_eq!(SwapEvent::from_logs(&program_id, &logs), [event, refunded]);
+}
+
+/// Creates and funds an account submitting transactions on behalf of the swap parties.
+async fn create_relayer(
+    context: &mut ProgramTestContext,
+) -> Result<Keypair, Box<dyn std::error::Error>> {
+    let relayer = Keypair::new();
+    let rent = context.banks_client.get_rent().await?;
+    let fund = system_instruction::transfer(
+        &context.payer.pubkey(),
+        &relayer.pubkey(),
+        rent.minimum_balance(0),
+    );
+    process_instructions(context, &[fund], &[]).await?;
+    Ok(relayer)
+}
+
+/// Submits a lamports payment setting aside `relayer_fee` and locking `watcher_reward`.
+async fn submit_payment_with_fees(
+    relayer_fee: u64,
+    watcher_reward: u64,
+) -> Result<InitializeValues, Box<dyn std::error::Error>> {
+    let mut values = initialize().await?;
+    values.relayer_fee = relayer_fee;
+    values.watcher_reward = watcher_reward;
+    let sender_account = values.sender_account.insecure_clone();
+    let payment = lamports_payment_instruction(&values, &values.sender);
+    process_instructions(&mut values.context, &[payment], &[&sender_account]).await?;
+    Ok(values)
+}
+
+fn relayed_receiver_spend_instruction(values: &InitializeValues, relayer: &Pubkey) -> Instruction {
+    instruction::relayed_receiver_spend(
+        &values.program_id,
+        relayer,
+        &values.receiver,
+        &values.sender,
+        &values.secret,
+        values.secret_hash_algorithm,
+        values.lock_time,
+        values.amount,
+        None,
+    )
+    .expect("valid secret")
+}
+
+#[tokio::test]
+async fn test_relayed_receiver_spend() -> Result<(), Box<dyn std::error::Error>> {
+    let relayer_fee = 1000;
+    let mut values = submit_payment_with_fees(relayer_fee, 0).await?;
+    assert_eq!(stored_payment(&mut values).await?.relayer_fee, relayer_fee);
+    let relayer = create_relayer(&mut values.context).await?;
+    let relayer_balance = values
+        .context
+        .banks_client
+        .get_balance(relayer.pubkey())
+        .await?;
+    let receiver_account_balance = values
+        .context
+        .banks_client
+        .get_balance(values.receiver)
+        .await?;
+
+    // The receiver neither signs nor pays for the transaction.
+    let spend = relayed_receiver_spend_instruction(&values, &relayer.pubkey());
+    assert!(!spend.accounts[3].is_signer);
+    process_instructions(&mut values.context, &[spend], &[&relayer]).await?;
+    assert_eq!(
+        values
+            .context
+            .banks_client
+            .get_balance(values.receiver)
+            .await?,
+        receiver_account_balance + values.amount - relayer_fee
+    );
+    assert_eq!(
+        values
+            .context
+            .banks_client
+            .get_balance(relayer.pubkey())
+            .await?,
+        relayer_balance + relayer_fee
+    );
+    let payment = stored_payment(&mut values).await?;
+    assert_eq!(payment.state, PaymentState::ReceiverSpent);
+    assert_eq!(payment.revealed_secret(), Some(values.secret.as_slice()));
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_receiver_spend_keeps_relayer_fee() -> Result<(), Box<dyn std::error::Error>> {
+    let mut values = submit_payment_with_fees(1000, 0).await?;
+    let receiver_account = values.receiver_account.insecure_clone();
+    let receiver_account_balance = values
+        .context
+        .banks_client
+        .get_balance(values.receiver)
+        .await?;
+    let spend = receiver_spend_instruction(&values);
+    process_instructions(&mut values.context, &[spend], &[&receiver_account]).await?;
+    assert_eq!(
+        values
+            .context
+            .banks_client
+            .get_balance(values.receiver)
+            .await?,
+        receiver_account_balance + values.amount
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_relayed_receiver_spend_other_receiver() -> Result<(), Box<dyn std::error::Error>> {
+    let mut values = submit_payment_with_fees(1000, 0).await?;
+    let relayer = create_relayer(&mut values.context).await?;
+    // The funds can only go to the receiver the payment was made to.
+    let mut spend = relayed_receiver_spend_instruction(&values, &relayer.pubkey());
+    spend.accounts[3].pubkey = relayer.pubkey();
+    let result = process_instructions(&mut values.context, &[spend], &[&relayer]).await;
+    assert_custom_error(result, SwapError::VaultAddressMismatch);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_payment_relayer_fee_not_lower_than_amount() -> Result<(), Box<dyn std::error::Error>>
+{
+    let mut values = initialize().await?;
+    values.relayer_fee = values.amount;
+    let sender_account = values.sender_account.insecure_clone();
+    let payment = lamports_payment_instruction(&values, &values.sender);
+    let result = process_instructions(&mut values.context, &[payment], &[&sender_account]).await;
+    assert_custom_error(result, SwapError::InvalidRelayerFee);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_spl_relayed_receiver_spend() -> Result<(), Box<dyn std::error::Error>> {
+    let relayer_fee = 100;
+    let mut spl_values = initialize_spl_token(spl_token::id(), &[]).await?;
+    spl_values.values.relayer_fee = relayer_fee;
+    let payment = spl_token_payment_instruction(&spl_values);
+    let values = &mut spl_values.values;
+    let sender_account = values.sender_account.insecure_clone();
+    process_instructions(&mut values.context, &[payment], &[&sender_account]).await?;
+
+    let relayer = create_relayer(&mut values.context).await?;
+    let relayer_token_account = create_token_account(
+        &mut values.context,
+        &values.token_program,
+        &spl_values.mint,
+        &spl_values.mint_extensions,
+        &relayer.pubkey(),
+    )
+    .await?;
+    let token_accounts = spl_values.token_accounts(spl_values.receiver_token_account);
+    let values = &mut spl_values.values;
+    let spend = |relayer_token_account| {
+        instruction::relayed_receiver_spend(
+            &values.program_id,
+            &relayer.pubkey(),
+            &values.receiver,
+            &values.sender,
+            &values.secret,
+            values.secret_hash_algorithm,
+            values.lock_time,
+            values.amount,
+            Some((&token_accounts, relayer_token_account)),
+        )
+        .expect("valid secret")
+    };
+    // The relayer fee needs a token account to be paid to.
+    let (spend_without_fee_account, spend) = (spend(None), spend(Some(&relayer_token_account)));
+    let result = process_instructions(
+        &mut values.context,
+        &[spend_without_fee_account],
+        &[&relayer],
+    )
+    .await;
+    assert_eq!(
+        result.map_err(|e| e.unwrap()),
+        Err(TransactionError::InstructionError(
+            0,
+            InstructionError::NotEnoughAccountKeys
+        ))
+    );
+    process_instructions(&mut values.context, &[spend], &[&relayer]).await?;
+
+    let context = &mut spl_values.values.context;
+    assert_eq!(
+        get_token_balance(context, &spl_values.receiver_token_account).await?,
+        spl_values.values.amount - relayer_fee
+    );
+    assert_eq!(
+        get_token_balance(context, &relayer_token_account).await?,
+        relayer_fee
+    );
+    assert_eq!(
+        get_token_balance(context, &spl_values.vault_token_account).await?,
+        0
+    );
+    Ok(())
+}
+
+fn relayed_sender_refund_instruction(values: &InitializeValues, relayer: &Pubkey) -> Instruction {
+    instruction::relayed_sender_refund(
+        &values.program_id,
+        relayer,
+        &values.sender,
+        &values.receiver,
+        values.secret_hash,
+        values.lock_time,
+        values.amount,
+        None,
+    )
+}
+
+#[tokio::test]
+async fn test_relayed_sender_refund() -> Result<(), Box<dyn std::error::Error>> {
+    let mut values = submit_payment().await?;
+    let relayer = create_relayer(&mut values.context).await?;
+    let refund = relayed_sender_refund_instruction(&values, &relayer.pubkey());
+    let result = process_instructions(&mut values.context, &[refund.clone()], &[&relayer]).await;
+    assert_custom_error(result, SwapError::WaitForLockTime);
+
+    set_unix_timestamp(&mut values.context, values.lock_time + 1).await?;
+    let sender_account_balance = values
+        .context
+        .banks_client
+        .get_balance(values.sender)
+        .await?;
+    let relayer_balance = values
+        .context
+        .banks_client
+        .get_balance(relayer.pubkey())
+        .await?;
+    // The sender neither signs nor pays for the transaction.
+    assert!(!refund.accounts[3].is_signer);
+    process_instructions(&mut values.context, &[refund], &[&relayer]).await?;
+    assert_eq!(
+        values
+            .context
+            .banks_client
+            .get_balance(values.sender)
+            .await?,
+        sender_account_balance + values.amount
+    );
+    assert_eq!(
+        values
+            .context
+            .banks_client
+            .get_balance(relayer.pubkey())
+            .await?,
+        relayer_balance
+    );
+    assert_eq!(
+        stored_payment(&mut values).await?.state,
+        PaymentState::SenderRefunded
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_relayed_sender_refund_other_sender() -> Result<(), Box<dyn std::error::Error>> {
+    let mut values = submit_payment().await?;
+    let relayer = create_relayer(&mut values.context).await?;
+    set_unix_timestamp(&mut values.context, values.lock_time + 1).await?;
+    // The funds can only go back to the sender the payment was made by.
+    let mut refund = relayed_sender_refund_instruction(&values, &relayer.pubkey());
+    refund.accounts[3].pubkey = relayer.pubkey();
+    let result = process_instructions(&mut values.context, &[refund], &[&relayer]).await;
+    assert_custom_error(result, SwapError::VaultAddressMismatch);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_spl_relayed_sender_refund() -> Result<(), Box<dyn std::error::Error>> {
+    let mut spl_values = submit_spl_token_payment().await?;
+    let token_accounts = spl_values.token_accounts(spl_values.sender_token_account);
+    let values = &mut spl_values.values;
+    let relayer = create_relayer(&mut values.context).await?;
+    set_unix_timestamp(&mut values.context, values.lock_time + 1).await?;
+    let refund = instruction::relayed_sender_refund(
+        &values.program_id,
+        &relayer.pubkey(),
+        &values.sender,
+        &values.receiver,
+        values.secret_hash,
+        values.lock_time,
+        values.amount,
+        Some(&token_accounts),
+    );
+    process_instructions(&mut values.context, &[refund], &[&relayer]).await?;
+
+    let context = &mut spl_values.values.context;
+    assert_eq!(
+        get_token_balance(context, &spl_values.vault_token_account).await?,
+        0
+    );
+    assert_eq!(
+        get_token_balance(context, &spl_values.sender_token_account).await?,
+        spl_values.values.amount
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_watcher_reward_relayed_receiver_spend() -> Result<(), Box<dyn std::error::Error>> {
+    let watcher_reward = 5000;
+    let mut values = submit_payment_with_fees(0, watcher_reward).await?;
+    assert_eq!(
+        stored_payment(&mut values).await?.watcher_reward,
+        watcher_reward
+    );
+    let rent = values.context.banks_client.get_rent().await?;
+    assert_eq!(
+        values
+            .context
+            .banks_client
+            .get_balance(values.vault_pda)
+            .await?,
+        rent.minimum_balance(0) + values.amount + watcher_reward
+    );
+    let relayer = create_relayer(&mut values.context).await?;
+    let relayer_balance = values
+        .context
+        .banks_client
+        .get_balance(relayer.pubkey())
+        .await?;
+    let receiver_account_balance = values
+        .context
+        .banks_client
+        .get_balance(values.receiver)
+        .await?;
+
+    let spend = relayed_receiver_spend_instruction(&values, &relayer.pubkey());
+    process_instructions(&mut values.context, &[spend], &[&relayer]).await?;
+    assert_eq!(
+        values
+            .context
+            .banks_client
+            .get_balance(values.receiver)
+            .await?,
+        receiver_account_balance + values.amount
+    );
+    assert_eq!(
+        values
+            .context
+            .banks_client
+            .get_balance(relayer.pubkey())
+            .await?,
+        relayer_balance + watcher_reward
+    );
+    assert_eq!(
+        values
+            .context
+            .banks_client
+            .get_balance(values.vault_pda)
+            .await?,
+        rent.minimum_balance(0)
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_watcher_reward_relayed_sender_refund() -> Result<(), Box<dyn std::error::Error>> {
+    let watcher_reward = 5000;
+    let mut values = submit_payment_with_fees(0, watcher_reward).await?;
+    let relayer = create_relayer(&mut values.context).await?;
+    set_unix_timestamp(&mut values.context, values.lock_time + 1).await?;
+    let relayer_balance = values
+        .context
+        .banks_client
+        .get_balance(relayer.pubkey())
+        .await?;
+    let sender_account_balance = values
+        .context
+        .banks_client
+        .get_balance(values.sender)
+        .await?;
+
+    let refund = relayed_sender_refund_instruction(&values, &relayer.pubkey());
+    process_instructions(&mut values.context, &[refund], &[&relayer]).await?;
+    assert_eq!(
+        values
+            .context
+            .banks_client
+            .get_balance(values.sender)
+            .await?,
+        sender_account_balance + values.amount
+    );
+    assert_eq!(
+        values
+            .context
+            .banks_client
+            .get_balance(relayer.pubkey())
+            .await?,
+        relayer_balance + watcher_reward
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_watcher_reward_wrong_reward() -> Result<(), Box<dyn std::error::Error>> {
+    // The reward is committed to by the payment hash, it can't be claimed against another one.
+    let mut values = submit_payment_with_fees(0, 5000).await?;
+    let sender_account = values.sender_account.insecure_clone();
+    let mut payment = stored_payment(&mut values).await?;
+    payment.watcher_reward = 6000;
+    let mut account = values
+        .context
+        .banks_client
+        .get_account(values.vault_pda_data)
+        .await?
+        .expect("account not found");
+    payment.pack_into(&mut account.data)?;
+    values
+        .context
+        .set_account(&values.vault_pda_data, &account.into());
+    set_unix_timestamp(&mut values.context, values.lock_time + 1).await?;
+    let refund = sender_refund_instruction(&values);
+    let result = process_instructions(&mut values.context, &[refund], &[&sender_account]).await;
+    assert_custom_error(result, SwapError::InvalidPaymentHash);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_no_watcher_reward_relayed_receiver_spend() -> Result<(), Box<dyn std::error::Error>> {
+    let mut values = submit_payment().await?;
+    assert_eq!(stored_payment(&mut values).await?.watcher_reward, 0);
+    let relayer = create_relayer(&mut values.context).await?;
+    let relayer_balance = values
+        .context
+        .banks_client
+        .get_balance(relayer.pubkey())
+        .await?;
+    let spend = relayed_receiver_spend_instruction(&values, &relayer.pubkey());
+    process_instructions(&mut values.context, &[spend], &[&relayer]).await?;
+    assert_eq!(
+        values
+            .context
+            .banks_client
+            .get_balance(relayer.pubkey())
+            .await?,
+        relayer_balance
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_watcher_reward_receiver_spend() -> Result<(), Box<dyn std::error::Error>> {
+    // The receiver submitting the spend itself isn't owed the reward, closing the payment returns
+    // it to the sender.
+    let watcher_reward = 5000;
+    let mut values = submit_payment_with_fees(0, watcher_reward).await?;
+    let receiver_account = values.receiver_account.insecure_clone();
+    let sender_account = values.sender_account.insecure_clone();
+    let receiver_account_balance = values
+        .context
+        .banks_client
+        .get_balance(values.receiver)
+        .await?;
+    let spend = receiver_spend_instruction(&values);
+    process_instructions(&mut values.context, &[spend], &[&receiver_account]).await?;
+    assert_eq!(
+        values
+            .context
+            .banks_client
+            .get_balance(values.receiver)
+            .await?,
+        receiver_account_balance + values.amount
+    );
+
+    let sender_account_balance = values
+        .context
+        .banks_client
+        .get_balance(values.sender)
+        .await?;
+    let close = close_payment_instruction(&values, values.amount, None);
+    process_instructions(&mut values.context, &[close], &[&sender_account]).await?;
+    assert_eq!(
+        values
+            .context
+            .banks_client
+            .get_balance(values.sender)
+            .await?,
+        sender_account_balance
+            + watcher_reward
+            + values.rent_exemption_lamports
+            + values.vault_rent_exemption_lamports
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_spl_watcher_reward_relayed_receiver_spend() -> Result<(), Box<dyn std::error::Error>>
+{
+    let watcher_reward = 5000;
+    let mut spl_values = initialize_spl_token(spl_token::id(), &[]).await?;
+    spl_values.values.watcher_reward = watcher_reward;
+    let payment = spl_token_payment_instruction(&spl_values);
+    let values = &mut spl_values.values;
+    let sender_account = values.sender_account.insecure_clone();
+    process_instructions(&mut values.context, &[payment], &[&sender_account]).await?;
+
+    let relayer = create_relayer(&mut values.context).await?;
+    let relayer_balance = spl_values
+        .values
+        .context
+        .banks_client
+        .get_balance(relayer.pubkey())
+        .await?;
+    let token_accounts = spl_values.token_accounts(spl_values.receiver_token_account);
+    let values = &mut spl_values.values;
+    let spend = instruction::relayed_receiver_spend(
+        &values.program_id,
+        &relayer.pubkey(),
+        &values.receiver,
+        &values.sender,
+        &values.secret,
+        values.secret_hash_algorithm,
+        values.lock_time,
+        values.amount,
+        // Without a relayer fee, the relayer needs no token account.
+        Some((&token_accounts, None)),
+    )
+    .expect("valid secret");
+    process_instructions(&mut values.context, &[spend], &[&relayer]).await?;
+
+    let context = &mut spl_values.values.context;
+    assert_eq!(
+        get_token_balance(context, &spl_values.receiver_token_account).await?,
+        spl_values.values.amount
+    );
+    assert_eq!(
+        context.banks_client.get_balance(relayer.pubkey()).await?,
+        relayer_balance + watcher_reward
+    );
+    Ok(())
+}
+
+/// Creates a dex fee account and configures it, funded so that lamports fees lower than its rent
+/// exemption can be paid to it.
+async fn configure_dex_fee_account(
+    values: &mut InitializeValues,
+) -> Result<Pubkey, Box<dyn std::error::Error>> {
+    let dex_fee_account = Pubkey::new_unique();
+    let rent = values.context.banks_client.get_rent().await?;
+    let fund = system_instruction::transfer(
+        &values.context.payer.pubkey(),
+        &dex_fee_account,
+        rent.minimum_balance(0),
+    );
+    process_instructions(&mut values.context, &[fund], &[]).await?;
+    let settings = ConfigSettings {
+        dex_fee_account,
+        ..ConfigSettings::default()
+    };
+    initialize_config(values, &settings).await?;
+    Ok(dex_fee_account)
+}
+
+#[tokio::test]
+async fn test_payment_with_dex_fee() -> Result<(), Box<dyn std::error::Error>> {
+    let dex_fee = 1000;
+    let mut values = initialize().await?;
+    let dex_fee_account = configure_dex_fee_account(&mut values).await?;
+    let dex_fee_account_balance = values
+        .context
+        .banks_client
+        .get_balance(dex_fee_account)
+        .await?;
+    let sender_account = values.sender_account.insecure_clone();
+    let payment = instruction::payment_with_dex_fee(
+        lamports_payment_instruction(&values, &values.sender),
+        dex_fee,
+        &dex_fee_account,
+    )
+    .expect("payment instruction");
+    // Even without a configured rate, the fee can't be skipped.
+    let free_payment = instruction::payment_with_dex_fee(
+        lamports_payment_instruction(&values, &values.sender),
+        0,
+        &dex_fee_account,
+    )
+    .expect("payment instruction");
+    let result =
+        process_instructions(&mut values.context, &[free_payment], &[&sender_account]).await;
+    assert_custom_error(result, SwapError::DexFeeTooLow);
+
+    process_instructions(&mut values.context, &[payment], &[&sender_account]).await?;
+    assert_eq!(
+        values
+            .context
+            .banks_client
+            .get_balance(dex_fee_account)
+            .await?,
+        dex_fee_account_balance + dex_fee
+    );
+    let rent = values.context.banks_client.get_rent().await?;
+    assert_eq!(
+        values
+            .context
+            .banks_client
+            .get_balance(values.vault_pda)
+            .await?,
+        rent.minimum_balance(0) + values.amount
+    );
+    let payment = stored_payment(&mut values).await?;
+    assert_eq!(payment.state, PaymentState::PaymentSent);
+    assert_eq!(payment.dex_fee, dex_fee);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_payment_with_dex_fee_wrong_account() -> Result<(), Box<dyn std::error::Error>> {
+    let mut values = initialize().await?;
+    configure_dex_fee_account(&mut values).await?;
+    let sender_account = values.sender_account.insecure_clone();
+    let receiver = values.receiver;
+    let payment = instruction::payment_with_dex_fee(
+        lamports_payment_instruction(&values, &values.sender),
+        1000,
+        &receiver,
+    )
+    .expect("payment instruction");
+    let result = process_instructions(&mut values.context, &[payment], &[&sender_account]).await;
+    assert_custom_error(result, SwapError::InvalidDexFeeAccount);
+    // Neither the fee nor the payment went through.
+    assert!(values
+        .context
+        .banks_client
+        .get_account(values.vault_pda_data)
+        .await?
+        .is_none());
+    // Only payments can carry a dex fee.
+    assert!(
+        instruction::payment_with_dex_fee(sender_refund_instruction(&values), 1000, &receiver)
+            .is_none()
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_spl_payment_with_dex_fee() -> Result<(), Box<dyn std::error::Error>> {
+    let dex_fee = 100;
+    let mut spl_values = initialize_spl_token(spl_token::id(), &[]).await?;
+    // The sender only holds the minted amount, which has to cover the fee as well.
+    spl_values.values.amount -= dex_fee;
+    let dex_fee_account = configure_dex_fee_account(&mut spl_values.values).await?;
+    let values = &mut spl_values.values;
+    let dex_fee_token_account = create_token_account(
+        &mut values.context,
+        &values.token_program,
+        &spl_values.mint,
+        &spl_values.mint_extensions,
+        &dex_fee_account,
+    )
+    .await?;
+    let payment = instruction::payment_with_dex_fee(
+        spl_token_payment_instruction(&spl_values),
+        dex_fee,
+        &dex_fee_token_account,
+    )
+    .expect("payment instruction");
+    let values = &mut spl_values.values;
+    let sender_account = values.sender_account.insecure_clone();
+    process_instructions(&mut values.context, &[payment], &[&sender_account]).await?;
+
+    let context = &mut spl_values.values.context;
+    assert_eq!(
+        get_token_balance(context, &dex_fee_token_account).await?,
+        dex_fee
+    );
+    assert_eq!(
+        get_token_balance(context, &spl_values.vault_token_account).await?,
+        spl_values.values.amount
+    );
+    assert_eq!(
+        get_token_balance(context, &spl_values.sender_token_account).await?,
+        0
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_spl_payment_with_dex_fee_wrong_owner() -> Result<(), Box<dyn std::error::Error>> {
+    let mut spl_values = initialize_spl_token(spl_token::id(), &[]).await?;
+    configure_dex_fee_account(&mut spl_values.values).await?;
+    let payment = instruction::payment_with_dex_fee(
+        spl_token_payment_instruction(&spl_values),
+        100,
+        &spl_values.receiver_token_account,
+    )
+    .expect("payment instruction");
+    let values = &mut spl_values.values;
+    let sender_account = values.sender_account.insecure_clone();
+    let result = process_instructions(&mut values.context, &[payment], &[&sender_account]).await;
+    assert_custom_error(result, SwapError::InvalidDexFeeAccount);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_initialize_config() -> Result<(), Box<dyn std::error::Error>> {
+    let mut values = initialize().await?;
+    let settings = ConfigSettings {
+        dex_fee_account: values.receiver,
+        dex_fee_rate: 10,
+        min_amount: 1000,
+        allowed_token_programs: vec![spl_token::id()],
+    };
+    initialize_config(&mut values, &settings).await?;
+    assert_eq!(
+        stored_config(&mut values).await?,
+        Config {
+            admin: values.context.payer.pubkey(),
+            pending_admin: Pubkey::default(),
+            settings: settings.clone(),
+            paused: false,
+        }
+    );
+
+    // The config can only be initialized once.
+    let payer = values.context.payer.pubkey();
+    let initialize = instruction::initialize_config(&values.program_id, &payer, &payer, settings);
+    values.context.get_new_latest_blockhash().await?;
+    let result = process_instructions(&mut values.context, &[initialize], &[]).await;
+    assert_custom_error(result, SwapError::InvalidConfigAccount);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_initialize_config_not_upgrade_authority() -> Result<(), Box<dyn std::error::Error>> {
+    let mut values = initialize().await?;
+    let receiver = values.receiver;
+    set_upgrade_authority(&mut values, &receiver).await?;
+    let sender_account = values.sender_account.insecure_clone();
+    let initialize = instruction::initialize_config(
+        &values.program_id,
+        &values.sender,
+        &values.sender,
+        ConfigSettings::default(),
+    );
+    let result = process_instructions(&mut values.context, &[initialize], &[&sender_account]).await;
+    assert_custom_error(result, SwapError::UnauthorizedConfigUpdate);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_initialize_config_admin() -> Result<(), Box<dyn std::error::Error>> {
+    let mut values = initialize().await?;
+    let payer = values.context.payer.pubkey();
+    set_upgrade_authority(&mut values, &payer).await?;
+    let initialize = |admin| {
+        let mut instruction = instruction::initialize_config(
+            &values.program_id,
+            &payer,
+            admin,
+            ConfigSettings::default(),
+        );
+        // Neither admin below signs, nobody holds the default pubkey anyway.
+        instruction.accounts[3].is_signer = false;
+        instruction
+    };
+    let (default_admin, unsigned_admin) =
+        (initialize(&Pubkey::default()), initialize(&values.receiver));
+    let result = process_instructions(&mut values.context, &[default_admin], &[]).await;
+    assert_custom_error(result, SwapError::InvalidConfigAdmin);
+    let result = process_instructions(&mut values.context, &[unsigned_admin], &[]).await;
+    assert_custom_error(result, SwapError::UnauthorizedConfigUpdate);
+
+    // An admin other than the upgrade authority signs too.
+    let receiver_account = values.receiver_account.insecure_clone();
+    let initialize = instruction::initialize_config(
+        &values.program_id,
+        &payer,
+        &values.receiver,
+        ConfigSettings::default(),
+    );
+    process_instructions(&mut values.context, &[initialize], &[&receiver_account]).await?;
+    assert_eq!(stored_config(&mut values).await?.admin, values.receiver);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_initialize_invalid_config() -> Result<(), Box<dyn std::error::Error>> {
+    let mut values = initialize().await?;
+    let settings = ConfigSettings {
+        dex_fee_rate: 10_001,
+        ..ConfigSettings::default()
+    };
+    let payer = values.context.payer.pubkey();
+    set_upgrade_authority(&mut values, &payer).await?;
+    let initialize = instruction::initialize_config(&values.program_id, &payer, &payer, settings);
+    let result = process_instructions(&mut values.context, &[initialize], &[]).await;
+    assert_custom_error(result, SwapError::InvalidConfig);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_update_config() -> Result<(), Box<dyn std::error::Error>> {
+    let mut values = initialize().await?;
+    initialize_config(&mut values, &ConfigSettings::default()).await?;
+    let settings = ConfigSettings {
+        min_amount: 5,
+        ..ConfigSettings::default()
+    };
+
+    let sender_account = values.sender_account.insecure_clone();
+    let update = instruction::update_config(&values.program_id, &values.sender, settings.clone());
+    let result = process_instructions(&mut values.context, &[update], &[&sender_account]).await;
+    assert_custom_error(result, SwapError::UnauthorizedConfigUpdate);
+
+    let payer = values.context.payer.pubkey();
+    let update = instruction::update_config(&values.program_id, &payer, settings.clone());
+    process_instructions(&mut values.context, &[update], &[]).await?;
+    assert_eq!(stored_config(&mut values).await?.settings, settings);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_update_uninitialized_config() -> Result<(), Box<dyn std::error::Error>> {
+    let mut values = initialize().await?;
+    let payer = values.context.payer.pubkey();
+    let update = instruction::update_config(&values.program_id, &payer, ConfigSettings::default());
+    let result = process_instructions(&mut values.context, &[update], &[]).await;
+    assert_custom_error(result, SwapError::InvalidConfigAccount);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_config_admin_handover() -> Result<(), Box<dyn std::error::Error>> {
+    let mut values = initialize().await?;
+    initialize_config(&mut values, &ConfigSettings::default()).await?;
+    let payer = values.context.payer.pubkey();
+    let propose = instruction::propose_config_admin(&values.program_id, &payer, &values.sender);
+    process_instructions(&mut values.context, &[propose], &[]).await?;
+    let config = stored_config(&mut values).await?;
+    assert_eq!(config.admin, payer);
+    assert_eq!(config.pending_admin, values.sender);
+
+    // Only the proposed admin can accept.
+    let receiver_account = values.receiver_account.insecure_clone();
+    let accept = instruction::accept_config_admin(&values.program_id, &values.receiver);
+    let result = process_instructions(&mut values.context, &[accept], &[&receiver_account]).await;
+    assert_custom_error(result, SwapError::UnauthorizedConfigUpdate);
+
+    let sender_account = values.sender_account.insecure_clone();
+    let accept = instruction::accept_config_admin(&values.program_id, &values.sender);
+    process_instructions(&mut values.context, &[accept], &[&sender_account]).await?;
+    let config = stored_config(&mut values).await?;
+    assert_eq!(config.admin, values.sender);
+    assert_eq!(config.pending_admin, Pubkey::default());
+
+    // The previous admin lost its authority.
+    let settings = ConfigSettings {
+        min_amount: 5,
+        ..ConfigSettings::default()
+    };
+    let update = instruction::update_config(&values.program_id, &payer, settings.clone());
+    let result = process_instructions(&mut values.context, &[update], &[]).await;
+    assert_custom_error(result, SwapError::UnauthorizedConfigUpdate);
+
+    let update = instruction::update_config(&values.program_id, &values.sender, settings.clone());
+    process_instructions(&mut values.context, &[update], &[&sender_account]).await?;
+    assert_eq!(stored_config(&mut values).await?.settings, settings);
+
+    // Once accepted, the proposal can't be accepted again.
+    let accept = instruction::accept_config_admin(&values.program_id, &values.sender);
+    values.context.get_new_latest_blockhash().await?;
+    let result = process_instructions(&mut values.context, &[accept], &[&sender_account]).await;
+    assert_custom_error(result, SwapError::UnauthorizedConfigUpdate);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_propose_default_config_admin() -> Result<(), Box<dyn std::error::Error>> {
+    let mut values = initialize().await?;
+    initialize_config(&mut values, &ConfigSettings::default()).await?;
+    let payer = values.context.payer.pubkey();
+    let propose = instruction::propose_config_admin(&values.program_id, &payer, &Pubkey::default());
+    let result = process_instructions(&mut values.context, &[propose], &[]).await;
+    assert_custom_error(result, SwapError::InvalidConfigAdmin);
+    assert_eq!(
+        stored_config(&mut values).await?.pending_admin,
+        Pubkey::default()
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_payment_below_min_amount() -> Result<(), Box<dyn std::error::Error>> {
+    let mut values = initialize().await?;
+    let settings = ConfigSettings {
+        min_amount: values.amount + 1,
+        ..ConfigSettings::default()
+    };
+    initialize_config(&mut values, &settings).await?;
+    let sender_account = values.sender_account.insecure_clone();
+    let payment = lamports_payment_instruction(&values, &values.sender);
+    let result = process_instructions(&mut values.context, &[payment], &[&sender_account]).await;
+    assert_custom_error(result, SwapError::AmountBelowMinimum);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_spl_payment_token_program_not_allowed() -> Result<(), Box<dyn std::error::Error>> {
+    let mut spl_values = initialize_spl_token(spl_token::id(), &[]).await?;
+    let settings = ConfigSettings {
+        allowed_token_programs: vec![spl_token_2022::id()],
+        ..ConfigSettings::default()
+    };
+    initialize_config(&mut spl_values.values, &settings).await?;
+    let payment = spl_token_payment_instruction(&spl_values);
+    let values = &mut spl_values.values;
+    let sender_account = values.sender_account.insecure_clone();
+    let result = process_instructions(&mut values.context, &[payment], &[&sender_account]).await;
+    assert_custom_error(result, SwapError::TokenProgramNotAllowed);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_payment_with_dex_fee_too_low() -> Result<(), Box<dyn std::error::Error>> {
+    let mut values = initialize().await?;
+    let dex_fee_account = Pubkey::new_unique();
+    let settings = ConfigSettings {
+        dex_fee_account,
+        dex_fee_rate: 100,
+        ..ConfigSettings::default()
+    };
+    initialize_config(&mut values, &settings).await?;
+    let sender_account = values.sender_account.insecure_clone();
+    let min_dex_fee = settings.min_dex_fee(values.amount);
+    let payment = instruction::payment_with_dex_fee(
+        lamports_payment_instruction(&values, &values.sender),
+        min_dex_fee - 1,
+        &dex_fee_account,
+    )
+    .expect("payment instruction");
+    let result = process_instructions(&mut values.context, &[payment], &[&sender_account]).await;
+    assert_custom_error(result, SwapError::DexFeeTooLow);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_payment_wrong_config_account() -> Result<(), Box<dyn std::error::Error>> {
+    let mut values = initialize().await?;
+    let sender_account = values.sender_account.insecure_clone();
+    let mut payment = lamports_payment_instruction(&values, &values.sender);
+    payment.accounts[4].pubkey = Pubkey::new_unique();
+    let result = process_instructions(&mut values.context, &[payment], &[&sender_account]).await;
+    assert_custom_error(result, SwapError::InvalidConfigAccount);
+    Ok(())
+}
+
+/// Initializes the config with the payer as the admin and pauses new payments.
+async fn pause_payments(values: &mut InitializeValues) -> Result<(), Box<dyn std::error::Error>> {
+    initialize_config(values, &ConfigSettings::default()).await?;
+    let payer = values.context.payer.pubkey();
+    let pause = instruction::set_paused(&values.program_id, &payer, true);
+    process_instructions(&mut values.context, &[pause], &[]).await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_paused_payment() -> Result<(), Box<dyn std::error::Error>> {
+    let mut values = initialize().await?;
+    initialize_config(&mut values, &ConfigSettings::default()).await?;
+    let sender_account = values.sender_account.insecure_clone();
+    let pause = instruction::set_paused(&values.program_id, &values.sender, true);
+    let result = process_instructions(&mut values.context, &[pause], &[&sender_account]).await;
+    assert_custom_error(result, SwapError::UnauthorizedConfigUpdate);
+
+    let payer = values.context.payer.pubkey();
+    let pause = instruction::set_paused(&values.program_id, &payer, true);
+    process_instructions(&mut values.context, &[pause], &[]).await?;
+    assert!(stored_config(&mut values).await?.paused);
+    let payment = lamports_payment_instruction(&values, &values.sender);
+    let result =
+        process_instructions(&mut values.context, &[payment.clone()], &[&sender_account]).await;
+    assert_custom_error(result, SwapError::PaymentsPaused);
+
+    let resume = instruction::set_paused(&values.program_id, &payer, false);
+    process_instructions(&mut values.context, &[resume], &[]).await?;
+    process_instructions(&mut values.context, &[payment], &[&sender_account]).await?;
+    assert_eq!(
+        stored_payment(&mut values).await?.state,
+        PaymentState::PaymentSent
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_spl_paused_payment() -> Result<(), Box<dyn std::error::Error>> {
+    let mut spl_values = initialize_spl_token(spl_token::id(), &[]).await?;
+    pause_payments(&mut spl_values.values).await?;
+    let payment = spl_token_payment_instruction(&spl_values);
+    let values = &mut spl_values.values;
+    let sender_account = values.sender_account.insecure_clone();
+    let result = process_instructions(&mut values.context, &[payment], &[&sender_account]).await;
+    assert_custom_error(result, SwapError::PaymentsPaused);
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_paused_receiver_spend() -> Result<(), Box<dyn std::error::Error>> {
+    let mut values = submit_payment().await?;
+    pause_payments(&mut values).await?;
+    let receiver_account = values.receiver_account.insecure_clone();
+    let spend = receiver_spend_instruction(&values);
+    process_instructions(&mut values.context, &[spend], &[&receiver_account]).await?;
+    assert_eq!(
+        stored_payment(&mut values).await?.state,
+        PaymentState::ReceiverSpent
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_paused_sender_refund() -> Result<(), Box<dyn std::error::Error>> {
+    let mut values = submit_payment().await?;
+    pause_payments(&mut values).await?;
+    set_unix_timestamp(&mut values.context, values.lock_time + 1).await?;
+    let sender_account = values.sender_account.insecure_clone();
+    let refund = sender_refund_instruction(&values);
+    process_instructions(&mut values.context, &[refund], &[&sender_account]).await?;
+    assert_eq!(
+        stored_payment(&mut values).await?.state,
+        PaymentState::SenderRefunded
+    );
+    Ok(())
+}